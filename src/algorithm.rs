@@ -1,195 +1,258 @@
+use crate::builder::{BoxedBuilder, DendrogramBuilder};
 use crate::cluster;
+use crate::compact::{CompactBuilder, CompactDendrogram};
 use crate::data;
+use crate::data::TypedClusterSummary;
 use crate::dendrogram;
-use rand::seq::SliceRandom;
+use crate::diagnostics::Diagnostics;
+use crate::error::{ClusteringError, MonotonicityViolation};
+use crate::neighbors;
 use rand::RngCore;
 use std::collections::BinaryHeap;
-use std::collections::HashSet;
 
-fn find_neighbors<D, R>(
-    data: &D,
-    init_iterations: Option<i32>,
-    rng: &mut R,
-) -> HashSet<(usize, usize)>
-where
-    D: data::IndexableData,
-    R: rand::Rng,
-{
-    let init_ite = init_iterations.unwrap_or(1);
-    assert!(
-        init_ite >= 1,
-        "Number of initilizating iterations must be at least 1."
-    );
-
-    let num_rows = data.get_num_rows();
-    let num_columns = data.get_num_columns();
-
-    let mut neighbors: HashSet<(usize, usize)> = HashSet::new();
-    let mut row_indices: Vec<usize> = (0..num_rows).collect();
-    let mut col_indices: Vec<usize> = (0..num_columns).collect();
-
-    for _ in 0..init_ite {
-        for c in 0..num_columns {
-            col_indices.shuffle(rng);
-
-            // we move the column at hand to the last position to make sure we always explore this configuration despite the shuffle
-            for k in 0..num_columns {
-                if col_indices[c] == k {
-                    col_indices[c] = col_indices[num_columns - 1];
-                    col_indices[num_columns - 1] = k;
-                    break;
-                }
-            }
-            row_indices.sort_unstable_by(|i, j| {
-                for c in &col_indices {
-                    let v1 = data.get_value(*i, *c);
-                    let v2 = data.get_value(*j, *c);
-                    if v1 < v2 {
-                        return std::cmp::Ordering::Less;
-                    } else if v2 > v1 {
-                        return std::cmp::Ordering::Greater;
-                    }
-                }
-                std::cmp::Ordering::Equal
-            });
-            for i in 0..num_rows - 1 {
-                let mut row1 = row_indices[i];
-                let mut row2 = row_indices[i + 1];
-                if row1 > row2 {
-                    std::mem::swap(&mut row1, &mut row2);
-                }
-                neighbors.insert((row1, row2));
-            }
-        }
-    }
-
-    neighbors
+/// What the main loop does when the distance between two clusters decreases after they have grown.
+enum MonotonicityCheck<'a> {
+    Ignore,
+    Fail,
+    Report(&'a mut Diagnostics),
 }
 
-fn find_umerged_cluster(clusters: &Vec<cluster::Cluster>, mut index: usize) -> usize {
+fn find_umerged_cluster<S, N>(clusters: &[cluster::Cluster<S, N>], mut index: usize) -> usize {
     while let Some(other) = clusters[index].merged_into {
         index = other;
     }
     index
 }
 
-fn clustering_main_loop(
-    mut clusters: Vec<cluster::Cluster>,
+fn clustering_main_loop<S: TypedClusterSummary, B: DendrogramBuilder>(
+    mut clusters: Vec<cluster::Cluster<S, B::Node>>,
     mut heap: BinaryHeap<cluster::Link>,
-) -> dendrogram::Dendrogram {
-    let mut last_cluster_idx = 0;
+    mut check: MonotonicityCheck,
+    mut builder: B,
+) -> Result<B::Output, ClusteringError> {
+    loop {
+        while let Some(link) = heap.pop() {
+            process_link(&mut clusters, &mut heap, link, &mut check, &mut builder)?;
+        }
 
-    while let Some(link) = heap.pop() {
-        let c1 = &clusters[link.cluster1_index];
-        let c2 = &clusters[link.cluster2_index];
+        // the neighbor graph might be disconnected, in which case we link the remaining clusters together
+        let unmerged: Vec<usize> = (0..clusters.len())
+            .filter(|i| clusters[*i].merged_into.is_none())
+            .collect();
+        if unmerged.len() <= 1 {
+            let top_cluster = clusters.swap_remove(unmerged[0]);
+            return Ok(builder.finish(top_cluster.dendrogram.unwrap()));
+        }
+        for pair in unmerged.windows(2) {
+            let c1 = &clusters[pair[0]];
+            let c2 = &clusters[pair[1]];
+            heap.push(cluster::Link {
+                distance: c1.distance(c2),
+                cluster1_index: pair[0],
+                cluster2_index: pair[1],
+                cluster1_summary_size: c1.summary_size(),
+                cluster2_summary_size: c2.summary_size(),
+            });
+        }
+    }
+}
 
-        match c1.merged_into {
-            None => {
-                match c2.merged_into {
-                    None => {
-                        let c1_len = c1.summary_size();
-                        let c2_len = c2.summary_size();
-                        if c1_len != link.cluster1_summary_size
-                            || c2_len != link.cluster2_summary_size
-                        {
-                            let new_distance = c1.distance(c2);
-                            if cfg!(debug_assertions) {
-                                assert!(new_distance >= link.distance, "distance function does not statisfy properties required for complete-linkage");
-                            }
+fn process_link<S: TypedClusterSummary, B: DendrogramBuilder>(
+    clusters: &mut [cluster::Cluster<S, B::Node>],
+    heap: &mut BinaryHeap<cluster::Link>,
+    link: cluster::Link,
+    check: &mut MonotonicityCheck,
+    builder: &mut B,
+) -> Result<(), ClusteringError> {
+    let c1 = &clusters[link.cluster1_index];
+    let c2 = &clusters[link.cluster2_index];
 
-                            // one of the two clusters has changed -> we need to update the distance
-                            heap.push(cluster::Link {
-                                distance: new_distance,
+    match c1.merged_into {
+        None => {
+            match c2.merged_into {
+                None => {
+                    let c1_len = c1.summary_size();
+                    let c2_len = c2.summary_size();
+                    if c1_len != link.cluster1_summary_size || c2_len != link.cluster2_summary_size
+                    {
+                        let new_distance = c1.distance(c2);
+                        if new_distance < link.distance {
+                            let violation = MonotonicityViolation {
                                 cluster1_index: link.cluster1_index,
                                 cluster2_index: link.cluster2_index,
-                                cluster1_summary_size: c1_len,
-                                cluster2_summary_size: c2_len,
-                            })
-                        } else {
-                            // we can merge the two clusters
-                            assert!(link.cluster1_index != link.cluster2_index);
-                            let (mut_c1, mut_c2) = if link.cluster1_index < link.cluster2_index {
-                                let (left, right) = clusters.split_at_mut(link.cluster2_index);
-                                (&mut left[link.cluster1_index], &mut right[0])
-                            } else {
-                                let (left, right) = clusters.split_at_mut(link.cluster1_index);
-                                (&mut right[0], &mut left[link.cluster2_index])
+                                previous_distance: link.distance,
+                                new_distance,
                             };
-
-                            let (src, dest, dest_idx) = if c1_len > c2_len {
-                                // we will merge into the cluster that has more categories to make it more likely that it doesn't change
-                                (mut_c2, mut_c1, link.cluster1_index)
-                            } else {
-                                (mut_c1, mut_c2, link.cluster2_index)
-                            };
-
-                            // merge the two clusters
-                            let dendro1 = dest.dendrogram.take().unwrap();
-                            let dendro2 = src.dendrogram.take().unwrap();
-                            let new_size = dendro1.size() + dendro2.size();
-                            dest.dendrogram = Some(dendrogram::Dendrogram::Node(
-                                Box::new(dendro1),
-                                Box::new(dendro2),
-                                link.distance,
-                                new_size,
-                            ));
-                            dest.summary.extend(&*src.summary);
-                            src.summary.clear();
-
-                            src.merged_into = Some(dest_idx);
-                            last_cluster_idx = dest_idx;
-                        }
-                    }
-                    Some(c2_parent_index) => {
-                        let unmerged_idx = find_umerged_cluster(&clusters, c2_parent_index);
-                        if unmerged_idx != link.cluster1_index {
-                            let unmerged = &clusters[unmerged_idx];
-                            heap.push(cluster::Link {
-                                distance: c1.distance(unmerged),
-                                cluster1_index: link.cluster1_index,
-                                cluster2_index: unmerged_idx,
-                                cluster1_summary_size: c1.summary_size(),
-                                cluster2_summary_size: unmerged.summary_size(),
-                            })
+                            match check {
+                                MonotonicityCheck::Ignore => {}
+                                MonotonicityCheck::Fail => {
+                                    return Err(ClusteringError::MonotonicityViolation(violation))
+                                }
+                                MonotonicityCheck::Report(diagnostics) => {
+                                    diagnostics.report(violation)
+                                }
+                            }
                         }
-                    }
-                }
-            }
-            Some(c1_parent_index) => match c2.merged_into {
-                None => {
-                    let unmerged_idx = find_umerged_cluster(&clusters, c1_parent_index);
-                    if unmerged_idx != link.cluster2_index {
-                        let unmerged = &clusters[unmerged_idx];
+
+                        // one of the two clusters has changed -> we need to update the distance
                         heap.push(cluster::Link {
-                            distance: c2.distance(unmerged),
-                            cluster1_index: unmerged_idx,
+                            distance: new_distance,
+                            cluster1_index: link.cluster1_index,
                             cluster2_index: link.cluster2_index,
-                            cluster1_summary_size: unmerged.summary_size(),
-                            cluster2_summary_size: c2.summary_size(),
+                            cluster1_summary_size: c1_len,
+                            cluster2_summary_size: c2_len,
                         })
+                    } else {
+                        // we can merge the two clusters
+                        assert!(link.cluster1_index != link.cluster2_index);
+                        let (mut_c1, mut_c2) = if link.cluster1_index < link.cluster2_index {
+                            let (left, right) = clusters.split_at_mut(link.cluster2_index);
+                            (&mut left[link.cluster1_index], &mut right[0])
+                        } else {
+                            let (left, right) = clusters.split_at_mut(link.cluster1_index);
+                            (&mut right[0], &mut left[link.cluster2_index])
+                        };
+
+                        let (src, dest, dest_idx) = if c1_len > c2_len {
+                            // we will merge into the cluster that has more categories to make it more likely that it doesn't change
+                            (mut_c2, mut_c1, link.cluster1_index)
+                        } else {
+                            (mut_c1, mut_c2, link.cluster2_index)
+                        };
+
+                        // merge the two clusters
+                        let dendro1 = dest.dendrogram.take().unwrap();
+                        let dendro2 = src.dendrogram.take().unwrap();
+                        dest.dendrogram = Some(builder.merge(dendro1, dendro2, link.distance));
+                        dest.summary.extend(&src.summary);
+                        src.summary.clear();
+
+                        src.merged_into = Some(dest_idx);
                     }
                 }
                 Some(c2_parent_index) => {
-                    let unmerged1_idx = find_umerged_cluster(&clusters, c1_parent_index);
-                    let unmerged2_idx = find_umerged_cluster(&clusters, c2_parent_index);
-                    if unmerged1_idx != unmerged2_idx {
-                        let unmerged1 = &clusters[unmerged1_idx];
-                        let unmerged2 = &clusters[unmerged1_idx];
+                    let unmerged_idx = find_umerged_cluster(clusters, c2_parent_index);
+                    if unmerged_idx != link.cluster1_index {
+                        let unmerged = &clusters[unmerged_idx];
                         heap.push(cluster::Link {
-                            distance: unmerged1.distance(unmerged2),
-                            cluster1_index: unmerged1_idx,
-                            cluster2_index: unmerged2_idx,
-                            cluster1_summary_size: unmerged1.summary_size(),
-                            cluster2_summary_size: unmerged2.summary_size(),
+                            distance: c1.distance(unmerged),
+                            cluster1_index: link.cluster1_index,
+                            cluster2_index: unmerged_idx,
+                            cluster1_summary_size: c1.summary_size(),
+                            cluster2_summary_size: unmerged.summary_size(),
                         })
                     }
                 }
-            },
+            }
         }
+        Some(c1_parent_index) => match c2.merged_into {
+            None => {
+                let unmerged_idx = find_umerged_cluster(clusters, c1_parent_index);
+                if unmerged_idx != link.cluster2_index {
+                    let unmerged = &clusters[unmerged_idx];
+                    heap.push(cluster::Link {
+                        distance: c2.distance(unmerged),
+                        cluster1_index: unmerged_idx,
+                        cluster2_index: link.cluster2_index,
+                        cluster1_summary_size: unmerged.summary_size(),
+                        cluster2_summary_size: c2.summary_size(),
+                    })
+                }
+            }
+            Some(c2_parent_index) => {
+                let unmerged1_idx = find_umerged_cluster(clusters, c1_parent_index);
+                let unmerged2_idx = find_umerged_cluster(clusters, c2_parent_index);
+                if unmerged1_idx != unmerged2_idx {
+                    let unmerged1 = &clusters[unmerged1_idx];
+                    let unmerged2 = &clusters[unmerged2_idx];
+                    heap.push(cluster::Link {
+                        distance: unmerged1.distance(unmerged2),
+                        cluster1_index: unmerged1_idx,
+                        cluster2_index: unmerged2_idx,
+                        cluster1_summary_size: unmerged1.summary_size(),
+                        cluster2_summary_size: unmerged2.summary_size(),
+                    })
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
+fn build_dendrogram<D, N, R, B>(
+    data: &D,
+    neighbor_finder: N,
+    rng: &mut R,
+    check: MonotonicityCheck,
+    mut builder: B,
+) -> Result<B::Output, ClusteringError>
+where
+    D: data::TypedIndexableData,
+    N: neighbors::NeighborFinder,
+    R: RngCore,
+    B: DendrogramBuilder,
+{
+    let num_rows = data.num_rows();
+    if num_rows == 0 {
+        return Err(ClusteringError::EmptyData);
+    }
+    let num_cols = data.num_columns();
+    if num_cols == 0 {
+        return Err(ClusteringError::NoColumns);
+    }
+
+    let clusters: Vec<cluster::Cluster<D::Summary, B::Node>> = (0..num_rows)
+        .map({
+            |r| cluster::Cluster {
+                merged_into: None,
+                summary: data.create_summary(r),
+                dendrogram: Some(builder.leaf(r)),
+            }
+        })
+        .collect();
+
+    let neighbors = neighbor_finder.find_neighbors(data, rng)?;
+    // custom finders can return anything, and a bad pair would make the main loop panic
+    if let Some((i, j)) = neighbors.iter().find(|(i, j)| i >= j || *j >= num_rows) {
+        return Err(ClusteringError::InvalidParameter(format!(
+            "the neighbor finder returned the pair ({}, {}), expected (i, j) with i < j < {}",
+            i, j, num_rows
+        )));
     }
 
-    let top_cluster = clusters.remove(last_cluster_idx);
-    top_cluster.dendrogram.unwrap()
+    let create_link = |(row_idx1, row_idx2): &(usize, usize)| cluster::Link {
+        distance: clusters[*row_idx1].distance(&clusters[*row_idx2]),
+        cluster1_index: *row_idx1,
+        cluster2_index: *row_idx2,
+        cluster1_summary_size: clusters[*row_idx1].summary_size(),
+        cluster2_summary_size: clusters[*row_idx2].summary_size(),
+    };
+
+    // the links are seeded in a fixed order, so that links at equal distances don't depend on the iteration order
+    // of the hash set, and a seeded rng gives the same dendrogram from one run to the next
+    let mut neighbors: Vec<(usize, usize)> = neighbors.into_iter().collect();
+    neighbors.sort_unstable();
+
+    #[cfg(not(feature = "rayon"))]
+    let links: Vec<cluster::Link> = neighbors.iter().map(create_link).collect();
+
+    #[cfg(feature = "rayon")]
+    let links: Vec<cluster::Link> = {
+        use rayon::prelude::*;
+        neighbors.par_iter().map(create_link).collect()
+    };
+
+    let heap: BinaryHeap<cluster::Link> = BinaryHeap::from(links);
+    clustering_main_loop(clusters, heap, check, builder)
+}
+
+/// The check used by the functions that panic: violations are errors in debug builds and ignored in release builds.
+fn default_check() -> MonotonicityCheck<'static> {
+    if cfg!(debug_assertions) {
+        MonotonicityCheck::Fail
+    } else {
+        MonotonicityCheck::Ignore
+    }
 }
 
 /// This function groups input data into clusters and returns the corresponding dendrogram.
@@ -197,8 +260,9 @@ fn clustering_main_loop(
 /// # Arguments
 ///
 /// * `data` - The data organized in rows and columns.
-/// * `init_iterations` - The number of initializing iterations.
-///    During initialization, the algorithm builds a sparse adjacency matrix by projecting the data differently through multiple shuffles/rotations of the columns.
+/// * `neighbor_finder` - The strategy used to find the initial candidate neighbors, e.g. [`crate::SortedProjection`].
+///   Passing `None` or `Some(init_iterations)` selects the default sorted projection with the given number of initializing iterations.
+///   During initialization, the algorithm builds a sparse adjacency matrix by projecting the data differently through multiple shuffles/rotations of the columns.
 /// * `rng` - A random number generator that will be used for the initialization step.
 ///
 /// # Returns
@@ -208,39 +272,103 @@ fn clustering_main_loop(
 /// * the dendograms of the two merged clusters.
 /// * the distance between the two merged clusters.
 /// * the size of the resulting cluster.
-pub fn create_dendrogram<D, R>(
+///
+/// # Panics
+///
+/// This panics if the data is empty or if a parameter is invalid. In debug builds, it also panics if the distance function does
+/// not satisfy the properties required for complete-linkage. Use [`try_create_dendrogram`] to get these errors as a `Result`.
+pub fn create_dendrogram<D, N, R>(
     data: &D,
-    init_iterations: Option<i32>,
+    neighbor_finder: N,
     rng: &mut R,
 ) -> dendrogram::Dendrogram
 where
-    D: data::IndexableData,
+    D: data::TypedIndexableData,
+    N: neighbors::NeighborFinder,
     R: RngCore,
 {
-    let num_rows = data.get_num_rows();
-    let num_cols = data.get_num_columns();
-    let clusters: Vec<cluster::Cluster> = (0..num_rows)
-        .map({
-            |r| cluster::Cluster {
-                merged_into: None,
-                summary: data.create_cluster_summary(r),
-                dendrogram: Some(dendrogram::Dendrogram::Leaf(r)),
-            }
-        })
-        .collect();
+    match build_dendrogram(data, neighbor_finder, rng, default_check(), BoxedBuilder) {
+        Ok(dendrogram) => dendrogram,
+        Err(e) => panic!("{}", e),
+    }
+}
 
-    let neighbors = find_neighbors(data, init_iterations, rng);
-
-    let mut heap: BinaryHeap<cluster::Link> = BinaryHeap::new();
-    for (row_idx1, row_idx2) in &neighbors {
-        heap.push(cluster::Link {
-            distance: clusters[*row_idx1].distance(&clusters[*row_idx2]),
-            cluster1_index: *row_idx1,
-            cluster2_index: *row_idx2,
-            cluster1_summary_size: num_cols,
-            cluster2_summary_size: num_cols,
-        });
+/// Same as [`create_dendrogram`], but returns an error instead of panicking.
+///
+/// The distance function is checked in release builds too: the first time the distance between two clusters decreases
+/// after they have grown, the clustering stops and returns [`ClusteringError::MonotonicityViolation`].
+pub fn try_create_dendrogram<D, N, R>(
+    data: &D,
+    neighbor_finder: N,
+    rng: &mut R,
+) -> Result<dendrogram::Dendrogram, ClusteringError>
+where
+    D: data::TypedIndexableData,
+    N: neighbors::NeighborFinder,
+    R: RngCore,
+{
+    build_dendrogram(
+        data,
+        neighbor_finder,
+        rng,
+        MonotonicityCheck::Fail,
+        BoxedBuilder,
+    )
+}
+
+/// Same as [`try_create_dendrogram`], but the clustering goes on when the distance function does not satisfy the properties
+/// required for complete-linkage. The violations are counted and reported in the returned [`Diagnostics`] instead,
+/// in release builds too.
+pub fn create_dendrogram_with_diagnostics<D, N, R>(
+    data: &D,
+    neighbor_finder: N,
+    rng: &mut R,
+) -> Result<(dendrogram::Dendrogram, Diagnostics), ClusteringError>
+where
+    D: data::TypedIndexableData,
+    N: neighbors::NeighborFinder,
+    R: RngCore,
+{
+    let mut diagnostics = Diagnostics::default();
+    let check = MonotonicityCheck::Report(&mut diagnostics);
+    let dendrogram = build_dendrogram(data, neighbor_finder, rng, check, BoxedBuilder)?;
+    Ok((dendrogram, diagnostics))
+}
+
+/// Same as [`create_dendrogram`], but the dendrogram is built directly into a [`CompactDendrogram`],
+/// which avoids allocating every node separately.
+///
+/// # Panics
+///
+/// Same as [`create_dendrogram`]. Use [`try_create_compact_dendrogram`] to get the errors as a `Result`.
+pub fn create_compact_dendrogram<D, N, R>(
+    data: &D,
+    neighbor_finder: N,
+    rng: &mut R,
+) -> CompactDendrogram
+where
+    D: data::TypedIndexableData,
+    N: neighbors::NeighborFinder,
+    R: RngCore,
+{
+    let builder = CompactBuilder::default();
+    match build_dendrogram(data, neighbor_finder, rng, default_check(), builder) {
+        Ok(dendrogram) => dendrogram,
+        Err(e) => panic!("{}", e),
     }
+}
 
-    clustering_main_loop(clusters, heap)
+/// Same as [`try_create_dendrogram`], but the dendrogram is built directly into a [`CompactDendrogram`].
+pub fn try_create_compact_dendrogram<D, N, R>(
+    data: &D,
+    neighbor_finder: N,
+    rng: &mut R,
+) -> Result<CompactDendrogram, ClusteringError>
+where
+    D: data::TypedIndexableData,
+    N: neighbors::NeighborFinder,
+    R: RngCore,
+{
+    let builder = CompactBuilder::default();
+    build_dendrogram(data, neighbor_finder, rng, MonotonicityCheck::Fail, builder)
 }
@@ -0,0 +1,289 @@
+use crate::data::{ClusterSummary, TypedClusterSummary, TypedIndexableData};
+use crate::error::ClusteringError;
+use std::any::Any;
+
+/// The layout of categorical columns into a bitset: each column gets as many consecutive bits as it has categories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitsetSchema {
+    cardinalities: Vec<usize>,
+    offsets: Vec<usize>,
+    num_bits: usize,
+}
+
+impl BitsetSchema {
+    /// # Arguments
+    ///
+    /// * `cardinalities` - The number of categories of each column. Categories are coded from 0 to cardinality - 1.
+    pub fn new(cardinalities: &[usize]) -> BitsetSchema {
+        let mut offsets = Vec::with_capacity(cardinalities.len());
+        let mut num_bits = 0;
+        for cardinality in cardinalities {
+            offsets.push(num_bits);
+            num_bits += cardinality;
+        }
+        BitsetSchema {
+            cardinalities: cardinalities.to_vec(),
+            offsets,
+            num_bits,
+        }
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.cardinalities.len()
+    }
+
+    pub fn cardinality(&self, column_index: usize) -> usize {
+        self.cardinalities[column_index]
+    }
+
+    /// The total number of categories, i.e. the number of bits of the summaries.
+    pub fn num_bits(&self) -> usize {
+        self.num_bits
+    }
+
+    /// The index of the bit standing for the given category of the given column.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the category is out of the column's cardinality.
+    pub fn bit(&self, column_index: usize, category: usize) -> usize {
+        assert!(
+            category < self.cardinalities[column_index],
+            "category {} is out of the cardinality of column {}",
+            category,
+            column_index
+        );
+        self.offsets[column_index] + category
+    }
+
+    /// The summary of an empty cluster.
+    pub(crate) fn empty_summary(&self) -> BitsetSummary {
+        BitsetSummary {
+            words: vec![0; self.num_bits.div_ceil(64)],
+        }
+    }
+
+    /// The summary of a row, given the category of each column.
+    ///
+    /// # Errors
+    ///
+    /// [`ClusteringError::InvalidParameter`] if the row doesn't have as many columns as the schema,
+    /// or if a category is out of its column's cardinality.
+    pub fn summarize(&self, categories: &[usize]) -> Result<BitsetSummary, ClusteringError> {
+        check_row(&self.cardinalities, categories).map_err(ClusteringError::InvalidParameter)?;
+        Ok(self.summarize_unchecked(categories))
+    }
+
+    /// Same as [`BitsetSchema::summarize`], for rows that have already been checked.
+    pub(crate) fn summarize_unchecked(&self, categories: &[usize]) -> BitsetSummary {
+        let mut summary = self.empty_summary();
+        for (offset, category) in self.offsets.iter().zip(categories) {
+            summary.insert(offset + category);
+        }
+        summary
+    }
+}
+
+/// A cluster summary holding the set of categories of the cluster in a bitset laid out by a [`BitsetSchema`].
+///
+/// The distance is the recommended `|A| + |B| - |A ∩ B|`, computed with popcounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitsetSummary {
+    words: Vec<u64>,
+}
+
+impl BitsetSummary {
+    pub fn insert(&mut self, bit: usize) {
+        self.words[bit / 64] |= 1 << (bit % 64);
+    }
+
+    pub fn contains(&self, bit: usize) -> bool {
+        self.words[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// The number of categories in the cluster.
+    pub fn summary_size(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn distance(&self, other: &BitsetSummary) -> f32 {
+        let intersection: u32 = self
+            .words
+            .iter()
+            .zip(&other.words)
+            .map(|(a, b)| (a & b).count_ones())
+            .sum();
+        (self.summary_size() + other.summary_size() - intersection as usize) as f32
+    }
+
+    pub fn extend(&mut self, other: &BitsetSummary) {
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a |= b;
+        }
+    }
+}
+
+impl ClusterSummary for BitsetSummary {
+    fn summary_size(&self) -> usize {
+        BitsetSummary::summary_size(self)
+    }
+
+    fn distance(&self, other: &dyn ClusterSummary) -> f32 {
+        let other = other.as_any().downcast_ref::<BitsetSummary>().unwrap();
+        BitsetSummary::distance(self, other)
+    }
+
+    fn extend(&mut self, other: &dyn ClusterSummary) {
+        let other = other.as_any().downcast_ref::<BitsetSummary>().unwrap();
+        BitsetSummary::extend(self, other)
+    }
+
+    fn clear(&mut self) {
+        self.words = Vec::new();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl TypedClusterSummary for BitsetSummary {
+    fn summary_size(&self) -> usize {
+        BitsetSummary::summary_size(self)
+    }
+
+    fn distance(&self, other: &BitsetSummary) -> f32 {
+        BitsetSummary::distance(self, other)
+    }
+
+    fn extend(&mut self, other: &BitsetSummary) {
+        BitsetSummary::extend(self, other)
+    }
+
+    fn clear(&mut self) {
+        self.words = Vec::new();
+    }
+}
+
+/// The cardinality of each column, from its largest category.
+pub(crate) fn infer_cardinalities(rows: &[Vec<usize>]) -> Vec<usize> {
+    let num_columns = rows.first().map_or(0, |row| row.len());
+    let mut cardinalities = vec![0; num_columns];
+    for row in rows {
+        for (cardinality, category) in cardinalities.iter_mut().zip(row) {
+            *cardinality = (*cardinality).max(category + 1);
+        }
+    }
+    cardinalities
+}
+
+/// Check the number of columns of a row and its categories against the cardinalities.
+pub(crate) fn check_row(cardinalities: &[usize], row: &[usize]) -> Result<(), String> {
+    if row.len() != cardinalities.len() {
+        return Err(format!(
+            "the row has {} columns, expected {}",
+            row.len(),
+            cardinalities.len()
+        ));
+    }
+    for (c, (category, cardinality)) in row.iter().zip(cardinalities).enumerate() {
+        if category >= cardinality {
+            return Err(format!(
+                "category {} is out of the cardinality of column {}",
+                category, c
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check the rows against the cardinalities and concatenate them.
+fn flatten_rows(
+    cardinalities: &[usize],
+    rows: &[Vec<usize>],
+) -> Result<Vec<usize>, ClusteringError> {
+    let mut categories = Vec::with_capacity(rows.len() * cardinalities.len());
+    for (r, row) in rows.iter().enumerate() {
+        check_row(cardinalities, row)
+            .map_err(|e| ClusteringError::InvalidParameter(format!("row {}: {}", r, e)))?;
+        categories.extend_from_slice(row);
+    }
+    Ok(categories)
+}
+
+/// Rows of integer-coded categories, ready to be clustered with [`BitsetSummary`] summaries.
+pub struct BitsetData {
+    schema: BitsetSchema,
+    // row-major categories
+    categories: Vec<usize>,
+    // stored rather than derived from the categories, which are empty when there are no columns
+    num_rows: usize,
+}
+
+impl BitsetData {
+    /// Wrap the rows, inferring the cardinality of each column from its largest category.
+    ///
+    /// # Errors
+    ///
+    /// [`ClusteringError::InvalidParameter`] if the rows don't all have the same number of columns.
+    pub fn new(rows: &[Vec<usize>]) -> Result<BitsetData, ClusteringError> {
+        BitsetData::with_schema(BitsetSchema::new(&infer_cardinalities(rows)), rows)
+    }
+
+    /// Wrap the rows with a given schema.
+    ///
+    /// # Errors
+    ///
+    /// [`ClusteringError::InvalidParameter`] if a row doesn't have as many columns as the schema,
+    /// or if a category is out of its column's cardinality.
+    pub fn with_schema(
+        schema: BitsetSchema,
+        rows: &[Vec<usize>],
+    ) -> Result<BitsetData, ClusteringError> {
+        let categories = flatten_rows(&schema.cardinalities, rows)?;
+        Ok(BitsetData {
+            schema,
+            categories,
+            num_rows: rows.len(),
+        })
+    }
+
+    pub fn schema(&self) -> &BitsetSchema {
+        &self.schema
+    }
+
+    fn row(&self, row_index: usize) -> &[usize] {
+        let num_columns = self.schema.num_columns();
+        &self.categories[row_index * num_columns..(row_index + 1) * num_columns]
+    }
+}
+
+impl TypedIndexableData for BitsetData {
+    type Summary = BitsetSummary;
+
+    fn value(&self, row_index: usize, column_index: usize) -> f32 {
+        self.row(row_index)[column_index] as f32
+    }
+
+    fn num_columns(&self) -> usize {
+        self.schema.num_columns()
+    }
+
+    fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    fn create_summary(&self, row_index: usize) -> BitsetSummary {
+        self.schema.summarize_unchecked(self.row(row_index))
+    }
+
+    fn tokens(&self, row_index: usize, tokens: &mut Vec<u64>) {
+        for (c, category) in self.row(row_index).iter().enumerate() {
+            tokens.push(self.schema.bit(c, *category) as u64);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::data::MaybeSendSync;
+use crate::dendrogram::Dendrogram;
+
+/// Builds the output of the clustering as clusters are merged, so that the main loop doesn't depend on
+/// the representation of the dendrogram.
+pub(crate) trait DendrogramBuilder {
+    /// A handle on a cluster of the dendrogram being built.
+    type Node: MaybeSendSync;
+    type Output;
+
+    /// Create the cluster made of a single row. This is called for every row, in order, before any merge.
+    fn leaf(&mut self, row_index: usize) -> Self::Node;
+
+    /// Merge two clusters at the given distance.
+    fn merge(&mut self, node1: Self::Node, node2: Self::Node, distance: f32) -> Self::Node;
+
+    /// Build the dendrogram from the cluster that contains all the rows.
+    fn finish(self, root: Self::Node) -> Self::Output;
+}
+
+/// Builds a [`Dendrogram`], with one heap allocation per node.
+pub(crate) struct BoxedBuilder;
+
+impl DendrogramBuilder for BoxedBuilder {
+    type Node = Dendrogram;
+    type Output = Dendrogram;
+
+    fn leaf(&mut self, row_index: usize) -> Dendrogram {
+        Dendrogram::Leaf(row_index)
+    }
+
+    fn merge(&mut self, node1: Dendrogram, node2: Dendrogram, distance: f32) -> Dendrogram {
+        let new_size = node1.size() + node2.size();
+        Dendrogram::Node(Box::new(node1), Box::new(node2), distance, new_size)
+    }
+
+    fn finish(self, root: Dendrogram) -> Dendrogram {
+        root
+    }
+}
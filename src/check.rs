@@ -0,0 +1,203 @@
+use crate::data::{TypedClusterSummary, TypedIndexableData};
+use rand::{Rng, RngCore};
+
+/// The maximum number of rows in the clusters built by [`check_summary`].
+const MAX_SAMPLED_CLUSTER_SIZE: usize = 8;
+
+/// A property of [`crate::ClusterSummary`] that [`check_summary`] found broken.
+/// Clusters are described by the indices of the rows they were built from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SummaryViolation {
+    /// `d(x1 U x2, y1 U y2) < d(x1, y1)`, whereas complete-linkage requires `d(x1 U x2, y1 U y2) >= d(x1, y1)`.
+    Monotonicity {
+        x1: Vec<usize>,
+        x2: Vec<usize>,
+        y1: Vec<usize>,
+        y2: Vec<usize>,
+        distance_before: f32,
+        distance_after: f32,
+    },
+    /// `d(x, y) != d(y, x)`.
+    Asymmetry {
+        x: Vec<usize>,
+        y: Vec<usize>,
+        distance_xy: f32,
+        distance_yx: f32,
+    },
+    /// `summary_size` decreased when the summary of `x` was extended with the summary of `y`.
+    SummarySizeDecreased {
+        x: Vec<usize>,
+        y: Vec<usize>,
+        size_before: usize,
+        size_after: usize,
+    },
+    /// `d(x, x)` is negative or not finite.
+    SelfDistance { x: Vec<usize>, distance: f32 },
+}
+
+/// The result of [`check_summary`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SummaryReport {
+    /// The number of properties checked.
+    pub num_checks: usize,
+    pub violations: Vec<SummaryViolation>,
+}
+
+impl SummaryReport {
+    /// Whether no violation was found.
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+fn sample_rows<R: RngCore>(num_rows: usize, min_size: usize, rng: &mut R) -> Vec<usize> {
+    let size = rng.gen_range(min_size..=MAX_SAMPLED_CLUSTER_SIZE);
+    (0..size).map(|_| rng.gen_range(0..num_rows)).collect()
+}
+
+fn extend_checked<S: TypedClusterSummary>(
+    summary: &mut S,
+    x: &[usize],
+    other: &S,
+    y: &[usize],
+    report: &mut SummaryReport,
+) {
+    let size_before = summary.summary_size();
+    summary.extend(other);
+    let size_after = summary.summary_size();
+    report.num_checks += 1;
+    if size_after < size_before {
+        report
+            .violations
+            .push(SummaryViolation::SummarySizeDecreased {
+                x: x.to_vec(),
+                y: y.to_vec(),
+                size_before,
+                size_after,
+            });
+    }
+}
+
+/// Build the summary of a non-empty cluster by extending row summaries one by one, like the algorithm does.
+fn build<D: TypedIndexableData>(
+    data: &D,
+    rows: &[usize],
+    report: &mut SummaryReport,
+) -> D::Summary {
+    let mut summary = data.create_summary(rows[0]);
+    for i in 1..rows.len() {
+        let other = data.create_summary(rows[i]);
+        extend_checked(&mut summary, &rows[..i], &other, &rows[i..=i], report);
+    }
+    summary
+}
+
+fn union<D: TypedIndexableData>(
+    data: &D,
+    rows1: &[usize],
+    rows2: &[usize],
+    report: &mut SummaryReport,
+) -> D::Summary {
+    let mut summary = build(data, rows1, report);
+    if !rows2.is_empty() {
+        let other = build(data, rows2, report);
+        extend_checked(&mut summary, rows1, &other, rows2, report);
+    }
+    summary
+}
+
+fn check_monotonicity<S: TypedClusterSummary>(
+    x: (&[usize], &[usize], &S),
+    y: (&[usize], &[usize], &S),
+    distance_before: f32,
+    report: &mut SummaryReport,
+) {
+    let distance_after = x.2.distance(y.2);
+    report.num_checks += 1;
+    if distance_after < distance_before || distance_after.is_nan() || distance_before.is_nan() {
+        report.violations.push(SummaryViolation::Monotonicity {
+            x1: x.0.to_vec(),
+            x2: x.1.to_vec(),
+            y1: y.0.to_vec(),
+            y2: y.1.to_vec(),
+            distance_before,
+            distance_after,
+        });
+    }
+}
+
+/// This checks that your [`crate::ClusterSummary`] or [`TypedClusterSummary`] implementation satisfies the properties the algorithm relies on,
+/// on clusters made of randomly sampled rows. Call it from your unit tests.
+///
+/// The following properties are checked:
+///
+/// * `d(x1 U x2, y1 U y2) >= d(x1, y1)`, including when `x2` or `y2` are empty.
+/// * `d(x, y) == d(y, x)`.
+/// * `summary_size` never decreases when a summary is extended.
+/// * `d(x, x)` is finite and non-negative, and `d(x, x U y) >= d(x, x)`.
+///
+/// # Arguments
+///
+/// * `data` - The data the summaries are created from.
+/// * `num_samples` - The number of random cluster quadruplets `(x1, x2, y1, y2)` to check.
+/// * `rng` - A random number generator used to sample the rows.
+///
+/// # Returns
+///
+/// The violations found. An empty dataset yields an empty report.
+pub fn check_summary<D, R>(data: &D, num_samples: usize, rng: &mut R) -> SummaryReport
+where
+    D: TypedIndexableData,
+    R: RngCore,
+{
+    let mut report = SummaryReport::default();
+    let num_rows = data.num_rows();
+    if num_rows == 0 {
+        return report;
+    }
+
+    for _ in 0..num_samples {
+        let x1 = sample_rows(num_rows, 1, rng);
+        let x2 = sample_rows(num_rows, 0, rng);
+        let y1 = sample_rows(num_rows, 1, rng);
+        let y2 = sample_rows(num_rows, 0, rng);
+
+        let sx1 = build(data, &x1, &mut report);
+        let sy1 = build(data, &y1, &mut report);
+        let distance_xy = sx1.distance(&sy1);
+
+        let distance_yx = sy1.distance(&sx1);
+        report.num_checks += 1;
+        if distance_xy != distance_yx {
+            report.violations.push(SummaryViolation::Asymmetry {
+                x: x1.clone(),
+                y: y1.clone(),
+                distance_xy,
+                distance_yx,
+            });
+        }
+
+        let sx = union(data, &x1, &x2, &mut report);
+        let sy = union(data, &y1, &y2, &mut report);
+        check_monotonicity((&x1, &x2, &sx), (&y1, &y2, &sy), distance_xy, &mut report);
+
+        let self_distance = sx1.distance(&sx1);
+        report.num_checks += 1;
+        if !self_distance.is_finite() || self_distance < 0.0 {
+            report.violations.push(SummaryViolation::SelfDistance {
+                x: x1.clone(),
+                distance: self_distance,
+            });
+        }
+
+        let sxy = union(data, &x1, &y1, &mut report);
+        check_monotonicity(
+            (&x1, &[], &sx1),
+            (&x1, &y1, &sxy),
+            self_distance,
+            &mut report,
+        );
+    }
+
+    report
+}
@@ -1,11 +1,12 @@
-use crate::data::ClusterSummary;
-use crate::dendrogram::Dendrogram;
+use crate::data::TypedClusterSummary;
 use std::cmp::{Ord, Ordering, PartialEq, PartialOrd};
 
-pub(crate) struct Cluster {
-    pub(crate) summary: Box<dyn ClusterSummary>,
+/// A cluster being built, `S` being the type of its summary and `N` the type of its node in the dendrogram.
+/// See [`crate::builder::DendrogramBuilder`].
+pub(crate) struct Cluster<S, N> {
+    pub(crate) summary: S,
     pub(crate) merged_into: Option<usize>,
-    pub(crate) dendrogram: Option<Dendrogram>,
+    pub(crate) dendrogram: Option<N>,
 }
 
 pub(crate) struct Link {
@@ -18,7 +19,7 @@ pub(crate) struct Link {
 
 impl PartialEq for Link {
     fn eq(&self, other: &Link) -> bool {
-        return self.distance == other.distance;
+        self.distance == other.distance
     }
 }
 
@@ -26,8 +27,7 @@ impl Eq for Link {}
 
 impl PartialOrd for Link {
     fn partial_cmp(&self, other: &Link) -> Option<Ordering> {
-        // Reverse the order for a min-heap
-        other.distance.partial_cmp(&self.distance)
+        Some(self.cmp(other))
     }
 }
 
@@ -38,12 +38,12 @@ impl Ord for Link {
     }
 }
 
-impl Cluster {
+impl<S: TypedClusterSummary, N> Cluster<S, N> {
     pub(crate) fn summary_size(&self) -> usize {
         self.summary.summary_size()
     }
 
-    pub(crate) fn distance(&self, other: &Cluster) -> f32 {
-        self.summary.distance(&*other.summary)
+    pub(crate) fn distance(&self, other: &Cluster<S, N>) -> f32 {
+        self.summary.distance(&other.summary)
     }
 }
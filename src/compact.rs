@@ -0,0 +1,248 @@
+use crate::builder::DendrogramBuilder;
+use crate::dendrogram::{Dendrogram, Hierarchy};
+use crate::error::ClusteringError;
+use crate::linkage::merge_order;
+
+/// The parent of the root.
+const NO_PARENT: usize = usize::MAX;
+
+/// A dendrogram stored in flat arrays rather than in boxed nodes. It is returned by [`crate::create_compact_dendrogram`].
+///
+/// Nodes are identified by their index. As in SciPy's linkage matrices, the leaves are numbered by their row index,
+/// from 0 to n - 1, and the i-th merge creates the node n + i. Children always have a lower index than their parent,
+/// and the root is the last node.
+///
+/// All the cuts accept a `&CompactDendrogram` as well as a `&Dendrogram`, through the [`Hierarchy`] trait.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactDendrogram {
+    num_rows: usize,
+    // for each merge
+    children: Vec<(usize, usize)>,
+    distances: Vec<f32>,
+    // for each node, leaves included
+    sizes: Vec<usize>,
+    parents: Vec<usize>,
+}
+
+impl CompactDendrogram {
+    /// The number of rows, i.e. the number of leaves.
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    /// The number of nodes, leaves included.
+    pub fn num_nodes(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// The index of the node that contains all the rows.
+    pub fn root(&self) -> usize {
+        self.parents.len() - 1
+    }
+
+    pub fn is_leaf(&self, node: usize) -> bool {
+        node < self.num_rows
+    }
+
+    /// The number of rows in the cluster.
+    pub fn size(&self, node: usize) -> usize {
+        self.sizes[node]
+    }
+
+    /// The distance between the two merged clusters, or 0 for a leaf.
+    pub fn distance(&self, node: usize) -> f32 {
+        if self.is_leaf(node) {
+            0.0
+        } else {
+            self.distances[node - self.num_rows]
+        }
+    }
+
+    /// The two merged clusters, or `None` for a leaf.
+    pub fn children(&self, node: usize) -> Option<(usize, usize)> {
+        if self.is_leaf(node) {
+            None
+        } else {
+            Some(self.children[node - self.num_rows])
+        }
+    }
+
+    /// The node this node was merged into, or `None` for the root.
+    pub fn parent(&self, node: usize) -> Option<usize> {
+        match self.parents[node] {
+            NO_PARENT => None,
+            parent => Some(parent),
+        }
+    }
+
+    pub(crate) fn swap_children(&mut self, node: usize) {
+        let (cluster1, cluster2) = self.children[node - self.num_rows];
+        self.children[node - self.num_rows] = (cluster2, cluster1);
+    }
+
+    /// Iterate over all the nodes and leaves, each parent before its children.
+    pub fn pre_order(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut stack = vec![self.root()];
+        std::iter::from_fn(move || {
+            let current = stack.pop()?;
+            if let Some((cluster1, cluster2)) = self.children(current) {
+                stack.push(cluster2);
+                stack.push(cluster1);
+            }
+            Some(current)
+        })
+    }
+
+    /// Iterate over all the nodes and leaves, each parent after its children.
+    pub fn post_order(&self) -> impl Iterator<Item = usize> + '_ {
+        // the flag tells whether the children of the node have already been pushed
+        let mut stack = vec![(self.root(), false)];
+        std::iter::from_fn(move || loop {
+            let (current, expanded) = stack.pop()?;
+            match self.children(current) {
+                Some((cluster1, cluster2)) if !expanded => {
+                    stack.push((current, true));
+                    stack.push((cluster2, false));
+                    stack.push((cluster1, false));
+                }
+                _ => return Some(current),
+            }
+        })
+    }
+
+    /// Iterate over the row indices of the leaves, from left to right.
+    pub fn leaves(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pre_order().filter(|node| self.is_leaf(*node))
+    }
+
+    /// Convert a boxed dendrogram into a compact one. The nodes are numbered as the clusters of [`Dendrogram::to_linkage_matrix`].
+    ///
+    /// # Errors
+    ///
+    /// [`ClusteringError::InvalidParameter`] if the leaves are not numbered from 0 to n - 1, which never happens
+    /// with the dendrograms returned by create_dendrogram.
+    pub fn from_dendrogram(dendrogram: &Dendrogram) -> Result<CompactDendrogram, ClusteringError> {
+        let num_rows = dendrogram.size();
+        let mut builder = CompactBuilder::default();
+        for row_index in 0..num_rows {
+            builder.leaf(row_index);
+        }
+
+        let mut seen = vec![false; num_rows];
+        for row_index in dendrogram.leaves() {
+            if row_index >= num_rows || seen[row_index] {
+                return Err(ClusteringError::InvalidParameter(format!(
+                    "the leaves must be numbered from 0 to {}, found {}",
+                    num_rows - 1,
+                    row_index
+                )));
+            }
+            seen[row_index] = true;
+        }
+
+        // number the nodes as in the linkage matrix
+        let mut root = 0;
+        for (id1, id2, distance, _) in merge_order(dendrogram, |_, post_order| post_order) {
+            root = builder.merge(id1, id2, distance);
+        }
+        Ok(builder.finish(root))
+    }
+
+    /// Convert the compact dendrogram into a boxed one.
+    pub fn to_dendrogram(&self) -> Dendrogram {
+        let mut nodes: Vec<Option<Dendrogram>> = (0..self.num_rows)
+            .map(|row_index| Some(Dendrogram::Leaf(row_index)))
+            .collect();
+
+        // children come before their parents
+        for (i, (id1, id2)) in self.children.iter().enumerate() {
+            let cluster1 = nodes[*id1].take().unwrap();
+            let cluster2 = nodes[*id2].take().unwrap();
+            nodes.push(Some(Dendrogram::Node(
+                Box::new(cluster1),
+                Box::new(cluster2),
+                self.distances[i],
+                self.sizes[self.num_rows + i],
+            )));
+        }
+        nodes.pop().unwrap().unwrap()
+    }
+}
+
+impl Hierarchy for &CompactDendrogram {
+    type Node = usize;
+
+    fn root(self) -> usize {
+        CompactDendrogram::root(self)
+    }
+
+    fn size(self, node: usize) -> usize {
+        CompactDendrogram::size(self, node)
+    }
+
+    fn distance(self, node: usize) -> f32 {
+        CompactDendrogram::distance(self, node)
+    }
+
+    fn children(self, node: usize) -> Option<(usize, usize)> {
+        CompactDendrogram::children(self, node)
+    }
+
+    fn row_index(self, node: usize) -> Option<usize> {
+        if self.is_leaf(node) {
+            Some(node)
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds a [`CompactDendrogram`] as clusters are merged.
+pub(crate) struct CompactBuilder {
+    dendrogram: CompactDendrogram,
+}
+
+impl Default for CompactBuilder {
+    fn default() -> CompactBuilder {
+        CompactBuilder {
+            dendrogram: CompactDendrogram {
+                num_rows: 0,
+                children: Vec::new(),
+                distances: Vec::new(),
+                sizes: Vec::new(),
+                parents: Vec::new(),
+            },
+        }
+    }
+}
+
+impl DendrogramBuilder for CompactBuilder {
+    type Node = usize;
+    type Output = CompactDendrogram;
+
+    fn leaf(&mut self, _row_index: usize) -> usize {
+        let dendrogram = &mut self.dendrogram;
+        dendrogram.num_rows += 1;
+        dendrogram.sizes.push(1);
+        dendrogram.parents.push(NO_PARENT);
+        dendrogram.parents.len() - 1
+    }
+
+    fn merge(&mut self, node1: usize, node2: usize, distance: f32) -> usize {
+        let dendrogram = &mut self.dendrogram;
+        let id = dendrogram.parents.len();
+        dendrogram.children.push((node1, node2));
+        dendrogram.distances.push(distance);
+        dendrogram
+            .sizes
+            .push(dendrogram.sizes[node1] + dendrogram.sizes[node2]);
+        dendrogram.parents.push(NO_PARENT);
+        dendrogram.parents[node1] = id;
+        dendrogram.parents[node2] = id;
+        id
+    }
+
+    fn finish(self, _root: usize) -> CompactDendrogram {
+        self.dendrogram
+    }
+}
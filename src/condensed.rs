@@ -0,0 +1,223 @@
+use crate::dendrogram::{assign_rows_to, group_rows_with_outliers, Hierarchy, OUTLIER};
+
+/// A cluster of the condensed dendrogram. See [`CondensedTree`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CondensedCluster {
+    /// The index of the parent cluster in [`CondensedTree::clusters`], or `None` for the root.
+    pub parent: Option<usize>,
+    /// The indices of the two clusters this cluster splits into, or `None` if its rows fall out of it before
+    /// it can split into two large enough clusters.
+    pub children: Option<(usize, usize)>,
+    /// `1 / distance` when the cluster appears, i.e. when its parent splits.
+    pub birth_lambda: f64,
+    /// The number of rows in the cluster when it appears.
+    pub size: usize,
+    /// The sum, over the rows of the cluster, of the time spent in the cluster, measured in `1 / distance`.
+    pub stability: f64,
+}
+
+/// A simplified version of the dendrogram, in the manner of HDBSCAN.
+///
+/// The dendrogram is walked from the root, with distances converted into densities `lambda = 1 / distance`.
+/// When a node splits into two children of at least `min_cluster_size` rows, two new clusters appear. Otherwise,
+/// the rows of the small children fall out of the cluster, which goes on as the large child, if any.
+/// The most stable clusters, i.e. the ones whose rows remain together over the widest range of distances,
+/// make a flat clustering.
+pub struct CondensedTree {
+    clusters: Vec<CondensedCluster>,
+    // for each row, the cluster it falls out of
+    exits: Vec<usize>,
+}
+
+/// Converts a merge distance into a density. Null distances are clamped to avoid infinite densities.
+fn lambda(distance: f32) -> f64 {
+    1.0 / (distance as f64).max(f64::EPSILON)
+}
+
+impl CondensedTree {
+    /// Build the condensed tree of a dendrogram.
+    ///
+    /// # Arguments
+    ///
+    /// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+    /// * `min_cluster_size` - The minimum size of a cluster. Smaller branches are considered as rows falling out of their parent cluster.
+    pub fn new<H: Hierarchy>(dendrogram: H, min_cluster_size: usize) -> CondensedTree {
+        let root = dendrogram.root();
+        let mut clusters = vec![CondensedCluster {
+            parent: None,
+            children: None,
+            birth_lambda: 0.0,
+            size: dendrogram.size(root),
+            stability: 0.0,
+        }];
+        let mut exits = vec![0; dendrogram.size(root)];
+
+        let mut stack: Vec<(H::Node, usize)> = vec![(root, 0)];
+        let mut leaf_stack = Vec::new();
+        while let Some((current, cluster_idx)) = stack.pop() {
+            let (c1, c2) = match dendrogram.children(current) {
+                Some(children) => children,
+                None => {
+                    // a cluster made of a single leaf, which never falls out of it
+                    exits[dendrogram.row_index(current).unwrap()] = cluster_idx;
+                    continue;
+                }
+            };
+            let split_lambda = lambda(dendrogram.distance(current));
+            let cluster = &mut clusters[cluster_idx];
+            let persistence = (split_lambda - cluster.birth_lambda).max(0.0);
+
+            let large1 = dendrogram.size(c1) >= min_cluster_size;
+            let large2 = dendrogram.size(c2) >= min_cluster_size;
+            if large1 && large2 {
+                cluster.stability += dendrogram.size(current) as f64 * persistence;
+                let first_child = clusters.len();
+                clusters[cluster_idx].children = Some((first_child, first_child + 1));
+                for (i, child) in [c1, c2].into_iter().enumerate() {
+                    clusters.push(CondensedCluster {
+                        parent: Some(cluster_idx),
+                        children: None,
+                        birth_lambda: split_lambda,
+                        size: dendrogram.size(child),
+                        stability: 0.0,
+                    });
+                    stack.push((child, first_child + i));
+                }
+                continue;
+            }
+
+            for child in [c1, c2] {
+                if dendrogram.size(child) >= min_cluster_size {
+                    // the cluster goes on as this child
+                    stack.push((child, cluster_idx));
+                } else {
+                    cluster.stability += dendrogram.size(child) as f64 * persistence;
+                    assign_rows_to(cluster_idx, dendrogram, child, &mut exits, &mut leaf_stack);
+                }
+            }
+        }
+
+        CondensedTree { clusters, exits }
+    }
+
+    /// The clusters of the condensed dendrogram. The root is the first one, and parents always come before their children.
+    pub fn clusters(&self) -> &[CondensedCluster] {
+        &self.clusters
+    }
+
+    /// The indices of the clusters that make the most stable flat clustering. A cluster is selected if its stability is at least
+    /// the sum of the stabilities selected among its descendants. As in HDBSCAN, the root cannot be selected.
+    pub fn select_clusters(&self) -> Vec<usize> {
+        let n = self.clusters.len();
+        let mut selected = vec![false; n];
+        let mut best_stability = vec![0.0; n];
+
+        // children come after their parents, so this is a bottom-up traversal
+        for i in (1..n).rev() {
+            let cluster = &self.clusters[i];
+            match cluster.children {
+                None => {
+                    selected[i] = true;
+                    best_stability[i] = cluster.stability;
+                }
+                Some((child1, child2)) => {
+                    let children_stability = best_stability[child1] + best_stability[child2];
+                    if cluster.stability >= children_stability {
+                        selected[i] = true;
+                        best_stability[i] = cluster.stability;
+                    } else {
+                        best_stability[i] = children_stability;
+                    }
+                }
+            }
+        }
+
+        // a cluster is kept only if none of its ancestors is selected
+        let mut under_selection = vec![false; n];
+        let mut result = Vec::new();
+        for i in 1..n {
+            let parent = self.clusters[i].parent.unwrap();
+            under_selection[i] = under_selection[parent] || (parent != 0 && selected[parent]);
+            if selected[i] && !under_selection[i] {
+                result.push(i);
+            }
+        }
+        result
+    }
+
+    /// This assigns the number of its selected cluster to each row, or [`OUTLIER`] to the rows that don't belong to any.
+    ///
+    /// # Arguments
+    ///
+    /// * `assignments` - cluster number for each row. This is passed as an argument for you to be able to reuse the vector across multiple calls.
+    ///   The vector will be resized if too small.
+    ///
+    /// # Returns
+    ///
+    /// The number of clusters found, noise excluded.
+    pub fn assign_rows(&self, assignments: &mut Vec<usize>) -> usize {
+        if self.exits.len() > assignments.len() {
+            assignments.resize(self.exits.len(), usize::MAX);
+        }
+
+        // parents come before their children, so labels can be propagated downwards
+        let mut labels = vec![OUTLIER; self.clusters.len()];
+        let selected = self.select_clusters();
+        for (cluster_n, cluster_idx) in selected.iter().enumerate() {
+            labels[*cluster_idx] = cluster_n;
+        }
+        for i in 1..self.clusters.len() {
+            if labels[i] == OUTLIER {
+                labels[i] = labels[self.clusters[i].parent.unwrap()];
+            }
+        }
+
+        for (row_index, cluster_idx) in self.exits.iter().enumerate() {
+            assignments[row_index] = labels[*cluster_idx];
+        }
+        selected.len()
+    }
+}
+
+/// This assigns a cluster number to each row by selecting the most stable clusters of the condensed dendrogram,
+/// or [`OUTLIER`] to the rows that don't belong to any. See [`CondensedTree`].
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `assignments` - cluster number for each row. This is passed as an argument for you to be able to reuse the vector across multiple calls.
+///   The vector will be resized if too small.
+/// * `min_cluster_size` - The minimum size of the clusters.
+///
+/// # Returns
+///
+/// The number of clusters found, noise excluded.
+///
+pub fn assign_rows_to_stable_clusters<H: Hierarchy>(
+    dendrogram: H,
+    assignments: &mut Vec<usize>,
+    min_cluster_size: usize,
+) -> usize {
+    CondensedTree::new(dendrogram, min_cluster_size).assign_rows(assignments)
+}
+
+/// This selects the most stable clusters of the condensed tree and returns them. See [`CondensedTree`].
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `min_cluster_size` - The minimum size of the clusters.
+///
+/// # Returns
+///
+/// The indices of the rows that belong to each returned cluster, and the indices of the rows labelled as noise.
+///
+pub fn find_stable_clusters<H: Hierarchy>(
+    dendrogram: H,
+    min_cluster_size: usize,
+) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let mut assignments = Vec::new();
+    let num_clusters =
+        assign_rows_to_stable_clusters(dendrogram, &mut assignments, min_cluster_size);
+    group_rows_with_outliers(&assignments, num_clusters)
+}
@@ -0,0 +1,186 @@
+use crate::dendrogram::Hierarchy;
+
+/// The steps of the non-recursive in-order traversal.
+enum Step<N> {
+    Visit(N, usize),
+    // the node separating the last leaf of its first child from the first leaf of its second child
+    Gap(N, usize),
+}
+
+/// An index answering lowest common ancestor (LCA) and cophenetic distance queries in constant time.
+///
+/// The LCA of two rows is the smallest cluster that contains both, and their cophenetic distance is the merge distance
+/// of this cluster. The LCA of two rows is the shallowest of the LCAs of the consecutive leaves between them,
+/// so the index is a sparse table of range minimum queries over the depths of these LCAs. It takes `O(n log n)` memory.
+pub struct CopheneticIndex<N> {
+    // for each row, its leaf and its position from left to right
+    leaves: Vec<N>,
+    positions: Vec<usize>,
+    // for each pair of consecutive leaves, their LCA and its depth and distance
+    gaps: Vec<N>,
+    depths: Vec<usize>,
+    distances: Vec<f32>,
+    // table[k][i] is the gap of minimum depth among gaps i to i + 2^k - 1
+    table: Vec<Vec<usize>>,
+}
+
+impl<N: Copy> CopheneticIndex<N> {
+    /// Build the index of a dendrogram.
+    ///
+    /// # Arguments
+    ///
+    /// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+    pub fn new<H: Hierarchy<Node = N>>(dendrogram: H) -> CopheneticIndex<N> {
+        let root = dendrogram.root();
+        let num_rows = dendrogram.size(root);
+        let mut leaves = vec![root; num_rows];
+        let mut positions = vec![0; num_rows];
+        let mut gaps = Vec::with_capacity(num_rows - 1);
+        let mut depths = Vec::with_capacity(num_rows - 1);
+        let mut distances = Vec::with_capacity(num_rows - 1);
+
+        let mut position = 0;
+        let mut stack = vec![Step::Visit(root, 0)];
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Visit(node, depth) => match dendrogram.children(node) {
+                    None => {
+                        let row_index = dendrogram.row_index(node).unwrap();
+                        leaves[row_index] = node;
+                        positions[row_index] = position;
+                        position += 1;
+                    }
+                    Some((cluster1, cluster2)) => {
+                        stack.push(Step::Visit(cluster2, depth + 1));
+                        stack.push(Step::Gap(node, depth));
+                        stack.push(Step::Visit(cluster1, depth + 1));
+                    }
+                },
+                Step::Gap(node, depth) => {
+                    gaps.push(node);
+                    depths.push(depth);
+                    distances.push(dendrogram.distance(node));
+                }
+            }
+        }
+
+        let mut table = vec![(0..gaps.len()).collect::<Vec<usize>>()];
+        let mut width = 1;
+        while 2 * width <= gaps.len() {
+            let previous = table.last().unwrap();
+            let level = (0..=gaps.len() - 2 * width)
+                .map(|i| {
+                    let (a, b) = (previous[i], previous[i + width]);
+                    if depths[b] < depths[a] {
+                        b
+                    } else {
+                        a
+                    }
+                })
+                .collect();
+            table.push(level);
+            width *= 2;
+        }
+
+        CopheneticIndex {
+            leaves,
+            positions,
+            gaps,
+            depths,
+            distances,
+            table,
+        }
+    }
+
+    /// The gap of minimum depth between two distinct rows.
+    fn lowest_gap(&self, row_index1: usize, row_index2: usize) -> usize {
+        let p1 = self.positions[row_index1];
+        let p2 = self.positions[row_index2];
+        let (start, end) = if p1 < p2 { (p1, p2) } else { (p2, p1) };
+        // the gaps between the leaves at positions start and end are start..end
+        let level = (usize::BITS - 1 - (end - start).leading_zeros()) as usize;
+        let a = self.table[level][start];
+        let b = self.table[level][end - (1 << level)];
+        if self.depths[b] < self.depths[a] {
+            b
+        } else {
+            a
+        }
+    }
+
+    /// The smallest cluster that contains both rows. This is the leaf itself if the rows are the same.
+    pub fn lca(&self, row_index1: usize, row_index2: usize) -> N {
+        if row_index1 == row_index2 {
+            return self.leaves[row_index1];
+        }
+        self.gaps[self.lowest_gap(row_index1, row_index2)]
+    }
+
+    /// The merge distance of the smallest cluster that contains both rows, or 0 if the rows are the same.
+    pub fn cophenetic_distance(&self, row_index1: usize, row_index2: usize) -> f32 {
+        if row_index1 == row_index2 {
+            return 0.0;
+        }
+        self.distances[self.lowest_gap(row_index1, row_index2)]
+    }
+
+    /// The Pearson correlation between the cophenetic distances and the given distances, over the given pairs of rows.
+    /// The higher the correlation, the more faithfully the dendrogram preserves the distances.
+    ///
+    /// # Arguments
+    ///
+    /// * `pairs` - The pairs of row indices, e.g. all the pairs or a random sample of them.
+    /// * `distance` - The distance between two rows.
+    ///
+    /// # Returns
+    ///
+    /// The correlation, or NaN if there are fewer than two pairs or if one of the two distances is constant.
+    pub fn correlation<I, F>(&self, pairs: I, distance: F) -> f64
+    where
+        I: IntoIterator<Item = (usize, usize)>,
+        F: Fn(usize, usize) -> f32,
+    {
+        let mut count = 0.0;
+        let (mut sum_x, mut sum_y) = (0.0, 0.0);
+        let (mut sum_xx, mut sum_yy, mut sum_xy) = (0.0, 0.0, 0.0);
+        for (i, j) in pairs {
+            let x = self.cophenetic_distance(i, j) as f64;
+            let y = distance(i, j) as f64;
+            count += 1.0;
+            sum_x += x;
+            sum_y += y;
+            sum_xx += x * x;
+            sum_yy += y * y;
+            sum_xy += x * y;
+        }
+        let covariance = count * sum_xy - sum_x * sum_y;
+        let variance_x = count * sum_xx - sum_x * sum_x;
+        let variance_y = count * sum_yy - sum_y * sum_y;
+        if count < 2.0 || variance_x <= 0.0 || variance_y <= 0.0 {
+            return f64::NAN;
+        }
+        covariance / (variance_x * variance_y).sqrt()
+    }
+}
+
+/// This computes the cophenetic correlation coefficient, i.e. the Pearson correlation between the cophenetic distances
+/// and the given distances, over all the pairs of rows. This takes `O(n^2)` time, so consider calling
+/// [`CopheneticIndex::correlation`] on a sample of pairs for large datasets.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `distance` - The distance between two rows.
+///
+/// # Returns
+///
+/// The correlation, or NaN if there are fewer than three rows or if one of the two distances is constant.
+pub fn cophenetic_correlation<H, F>(dendrogram: H, distance: F) -> f64
+where
+    H: Hierarchy,
+    F: Fn(usize, usize) -> f32,
+{
+    let num_rows = dendrogram.size(dendrogram.root());
+    let pairs = (0..num_rows).flat_map(|i| (i + 1..num_rows).map(move |j| (i, j)));
+    CopheneticIndex::new(dendrogram).correlation(pairs, distance)
+}
@@ -1,7 +1,30 @@
 use std::any::Any;
 
+/// With the `rayon` feature, data and cluster summaries are shared across threads, so they must be `Send + Sync`.
+#[cfg(feature = "rayon")]
+pub trait MaybeSendSync: Send + Sync {}
+#[cfg(feature = "rayon")]
+impl<T: Send + Sync + ?Sized> MaybeSendSync for T {}
+
+/// With the `rayon` feature, data and cluster summaries are shared across threads, so they must be `Send + Sync`.
+#[cfg(not(feature = "rayon"))]
+pub trait MaybeSendSync {}
+#[cfg(not(feature = "rayon"))]
+impl<T: ?Sized> MaybeSendSync for T {}
+
+/// The default tokens of a row: one per column, made of the column index and the value.
+pub(crate) fn column_tokens<F: Fn(usize) -> f32>(
+    num_columns: usize,
+    value: F,
+    tokens: &mut Vec<u64>,
+) {
+    for c in 0..num_columns {
+        tokens.push(((c as u64) << 32) | value(c).to_bits() as u64);
+    }
+}
+
 /// The trait you need to implement to provide the algorithm a distance and merging strategy.
-pub trait ClusterSummary {
+pub trait ClusterSummary: MaybeSendSync {
     /// The size of the summary structure (not of the cluster!).
     /// This increases when you add new categories to the cluster, and never decreases.
     fn summary_size(&self) -> usize;
@@ -21,9 +44,107 @@ pub trait ClusterSummary {
 }
 
 /// The trait you need to implement for the clustering algorithm to access your data.
-pub trait IndexableData {
+pub trait IndexableData: MaybeSendSync {
     fn get_value(&self, row_index: usize, column_index: usize) -> f32;
     fn get_num_columns(&self) -> usize;
     fn get_num_rows(&self) -> usize;
     fn create_cluster_summary(&self, row_index: usize) -> Box<dyn ClusterSummary>;
+
+    /// Append the categories of the given row to `tokens`, as opaque hashable tokens. Used by [`crate::MinHash`].
+    /// By default, each column contributes a token made of the column index and the value.
+    /// Override this if your rows hold sets of categories, or if your values don't fit into a single token.
+    fn get_tokens(&self, row_index: usize, tokens: &mut Vec<u64>) {
+        column_tokens(
+            self.get_num_columns(),
+            |c| self.get_value(row_index, c),
+            tokens,
+        )
+    }
+}
+
+/// The statically dispatched counterpart of [`ClusterSummary`]: `distance` and `extend` take the same summary type,
+/// so there is no downcasting and no boxing.
+///
+/// `Box<dyn ClusterSummary>` implements this trait, so the two APIs can be used interchangeably.
+pub trait TypedClusterSummary: MaybeSendSync {
+    /// See [`ClusterSummary::summary_size`].
+    fn summary_size(&self) -> usize;
+
+    /// See [`ClusterSummary::distance`].
+    fn distance(&self, other: &Self) -> f32;
+
+    /// See [`ClusterSummary::extend`].
+    fn extend(&mut self, other: &Self);
+
+    /// See [`ClusterSummary::clear`].
+    fn clear(&mut self);
+}
+
+impl TypedClusterSummary for Box<dyn ClusterSummary> {
+    fn summary_size(&self) -> usize {
+        ClusterSummary::summary_size(&**self)
+    }
+
+    fn distance(&self, other: &Self) -> f32 {
+        ClusterSummary::distance(&**self, &**other)
+    }
+
+    fn extend(&mut self, other: &Self) {
+        ClusterSummary::extend(&mut **self, &**other)
+    }
+
+    fn clear(&mut self) {
+        ClusterSummary::clear(&mut **self)
+    }
+}
+
+/// The statically dispatched counterpart of [`IndexableData`], with the type of the cluster summaries as an associated type.
+/// The clustering is monomorphized over this type, and mixing up summary types is a compile error.
+///
+/// Every [`IndexableData`] implements this trait with `Box<dyn ClusterSummary>` summaries.
+/// The methods are named differently from those of [`IndexableData`], so that calling them is never ambiguous
+/// when both traits are in scope.
+pub trait TypedIndexableData: MaybeSendSync {
+    type Summary: TypedClusterSummary;
+
+    /// See [`IndexableData::get_value`].
+    fn value(&self, row_index: usize, column_index: usize) -> f32;
+
+    /// See [`IndexableData::get_num_columns`].
+    fn num_columns(&self) -> usize;
+
+    /// See [`IndexableData::get_num_rows`].
+    fn num_rows(&self) -> usize;
+
+    /// See [`IndexableData::create_cluster_summary`].
+    fn create_summary(&self, row_index: usize) -> Self::Summary;
+
+    /// Append the categories of the given row to `tokens`, as opaque hashable tokens. See [`IndexableData::get_tokens`].
+    fn tokens(&self, row_index: usize, tokens: &mut Vec<u64>) {
+        column_tokens(self.num_columns(), |c| self.value(row_index, c), tokens)
+    }
+}
+
+impl<D: IndexableData + ?Sized> TypedIndexableData for D {
+    type Summary = Box<dyn ClusterSummary>;
+
+    fn value(&self, row_index: usize, column_index: usize) -> f32 {
+        self.get_value(row_index, column_index)
+    }
+
+    fn num_columns(&self) -> usize {
+        self.get_num_columns()
+    }
+
+    fn num_rows(&self) -> usize {
+        self.get_num_rows()
+    }
+
+    fn create_summary(&self, row_index: usize) -> Box<dyn ClusterSummary> {
+        self.create_cluster_summary(row_index)
+    }
+
+    fn tokens(&self, row_index: usize, tokens: &mut Vec<u64>) {
+        self.get_tokens(row_index, tokens)
+    }
 }
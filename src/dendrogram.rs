@@ -1,17 +1,173 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use std::mem::{replace, ManuallyDrop};
 
+/// The hierarchy of clusters returned by [`crate::create_dendrogram`].
+///
+/// Dendrograms can be very deep, so avoid walking them recursively. Use [`Dendrogram::pre_order`],
+/// [`Dendrogram::post_order`] or [`Dendrogram::leaves`] instead.
 pub enum Dendrogram {
     Leaf(usize),                                        // row index
     Node(Box<Dendrogram>, Box<Dendrogram>, f32, usize), // cluster1, cluster2, distance, size
 }
 
 impl Dendrogram {
+    /// The number of rows in the cluster.
     pub fn size(&self) -> usize {
         match self {
             Dendrogram::Leaf(_) => 1,
             Dendrogram::Node(_, _, _, s) => *s,
         }
     }
+
+    /// The distance between the two merged clusters, or 0 for a leaf.
+    pub fn distance(&self) -> f32 {
+        match self {
+            Dendrogram::Leaf(_) => 0.0,
+            Dendrogram::Node(_, _, d, _) => *d,
+        }
+    }
+
+    /// The two merged clusters, or `None` for a leaf.
+    pub fn children(&self) -> Option<(&Dendrogram, &Dendrogram)> {
+        match self {
+            Dendrogram::Leaf(_) => None,
+            Dendrogram::Node(cluster1, cluster2, _, _) => Some((cluster1, cluster2)),
+        }
+    }
+
+    /// The row index of a leaf, or `None` for a node.
+    pub fn row_index(&self) -> Option<usize> {
+        match self {
+            Dendrogram::Leaf(row_index) => Some(*row_index),
+            Dendrogram::Node(_, _, _, _) => None,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        matches!(self, Dendrogram::Leaf(_))
+    }
+
+    /// Iterate over all the nodes and leaves, each parent before its children.
+    pub fn pre_order(&self) -> PreOrder<'_> {
+        PreOrder { stack: vec![self] }
+    }
+
+    /// Iterate over all the nodes and leaves, each parent after its children.
+    pub fn post_order(&self) -> PostOrder<'_> {
+        PostOrder {
+            stack: vec![(self, false)],
+        }
+    }
+
+    /// Iterate over the row indices of the leaves, from left to right.
+    pub fn leaves(&self) -> Leaves<'_> {
+        Leaves {
+            nodes: self.pre_order(),
+        }
+    }
+}
+
+/// Read access to a dendrogram, whatever its representation. It is implemented by `&Dendrogram` and `&CompactDendrogram`,
+/// so that the cuts work on both.
+///
+/// A node is identified by a cheap handle, e.g. a reference to a [`Dendrogram`] or an index in a [`crate::CompactDendrogram`].
+pub trait Hierarchy: Copy {
+    type Node: Copy;
+
+    /// The node that contains all the rows.
+    fn root(self) -> Self::Node;
+
+    /// The number of rows in the cluster.
+    fn size(self, node: Self::Node) -> usize;
+
+    /// The distance between the two merged clusters, or 0 for a leaf.
+    fn distance(self, node: Self::Node) -> f32;
+
+    /// The two merged clusters, or `None` for a leaf.
+    fn children(self, node: Self::Node) -> Option<(Self::Node, Self::Node)>;
+
+    /// The row index of a leaf, or `None` for a node.
+    fn row_index(self, node: Self::Node) -> Option<usize>;
+}
+
+impl<'a> Hierarchy for &'a Dendrogram {
+    type Node = &'a Dendrogram;
+
+    fn root(self) -> &'a Dendrogram {
+        self
+    }
+
+    fn size(self, node: &'a Dendrogram) -> usize {
+        node.size()
+    }
+
+    fn distance(self, node: &'a Dendrogram) -> f32 {
+        node.distance()
+    }
+
+    fn children(self, node: &'a Dendrogram) -> Option<(&'a Dendrogram, &'a Dendrogram)> {
+        node.children()
+    }
+
+    fn row_index(self, node: &'a Dendrogram) -> Option<usize> {
+        node.row_index()
+    }
+}
+
+/// Non-recursive pre-order iterator returned by [`Dendrogram::pre_order`].
+pub struct PreOrder<'a> {
+    stack: Vec<&'a Dendrogram>,
+}
+
+impl<'a> Iterator for PreOrder<'a> {
+    type Item = &'a Dendrogram;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.stack.pop()?;
+        if let Dendrogram::Node(cluster1, cluster2, _, _) = current {
+            self.stack.push(cluster2);
+            self.stack.push(cluster1);
+        }
+        Some(current)
+    }
+}
+
+/// Non-recursive post-order iterator returned by [`Dendrogram::post_order`].
+pub struct PostOrder<'a> {
+    // the flag tells whether the children of the node have already been pushed
+    stack: Vec<(&'a Dendrogram, bool)>,
+}
+
+impl<'a> Iterator for PostOrder<'a> {
+    type Item = &'a Dendrogram;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (current, expanded) = self.stack.pop()?;
+            match current {
+                Dendrogram::Node(cluster1, cluster2, _, _) if !expanded => {
+                    self.stack.push((current, true));
+                    self.stack.push((cluster2, false));
+                    self.stack.push((cluster1, false));
+                }
+                _ => return Some(current),
+            }
+        }
+    }
+}
+
+/// Non-recursive iterator over the row indices of the leaves, returned by [`Dendrogram::leaves`].
+pub struct Leaves<'a> {
+    nodes: PreOrder<'a>,
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nodes.find_map(|node| node.row_index())
+    }
 }
 
 /// Rust's default's implementation of drop() is recursive, so we write a custom
@@ -37,21 +193,22 @@ impl Drop for Dendrogram {
     }
 }
 
-fn assign_rows_to<'a>(
+pub(crate) fn assign_rows_to<H: Hierarchy>(
     cluster_n: usize,
-    dendrogram: &'a Dendrogram,
-    assigments: &mut Vec<usize>,
-    stack: &mut Vec<&'a Dendrogram>,
+    tree: H,
+    node: H::Node,
+    assigments: &mut [usize],
+    stack: &mut Vec<H::Node>,
 ) {
     stack.clear();
-    stack.push(dendrogram);
+    stack.push(node);
 
     while let Some(current) = stack.pop() {
-        match current {
-            Dendrogram::Leaf(row_index) => {
-                assigments[*row_index] = cluster_n;
+        match tree.children(current) {
+            None => {
+                assigments[tree.row_index(current).unwrap()] = cluster_n;
             }
-            Dendrogram::Node(cluster1, cluster2, _, _) => {
+            Some((cluster1, cluster2)) => {
                 stack.push(cluster1);
                 stack.push(cluster2);
             }
@@ -59,75 +216,438 @@ fn assign_rows_to<'a>(
     }
 }
 
+/// Assign a cluster number to each row, diving into the nodes for which `split` returns true.
+fn assign_rows_splitting<H, F>(tree: H, assignments: &mut Vec<usize>, split: F) -> usize
+where
+    H: Hierarchy,
+    F: Fn(H::Node) -> bool,
+{
+    let root = tree.root();
+    if tree.size(root) > assignments.len() {
+        assignments.resize(tree.size(root), usize::MAX);
+    }
+
+    let mut cluster_n = 0;
+    let mut stack = Vec::new();
+    let mut same_cluster_stack = Vec::new();
+    stack.push(root);
+
+    while let Some(current) = stack.pop() {
+        match tree.children(current) {
+            None => {
+                assignments[tree.row_index(current).unwrap()] = cluster_n;
+                cluster_n += 1;
+            }
+            Some((cluster1, cluster2)) => {
+                if split(current) {
+                    // dive deeper
+                    stack.push(cluster1);
+                    stack.push(cluster2);
+                } else {
+                    assign_rows_to(
+                        cluster_n,
+                        tree,
+                        current,
+                        assignments,
+                        &mut same_cluster_stack,
+                    );
+                    cluster_n += 1;
+                }
+            }
+        }
+    }
+    cluster_n
+}
+
+/// Group the row indices by cluster number.
+pub(crate) fn group_rows(assignments: &[usize], num_clusters: usize) -> Vec<Vec<usize>> {
+    let mut clusters: Vec<Vec<usize>> = (0..num_clusters).map(|_| Vec::new()).collect();
+
+    for (row_idx, assignment) in assignments.iter().enumerate() {
+        clusters[*assignment].push(row_idx)
+    }
+    clusters
+}
+
 /// This assigns a cluster number to each row under the constraint that all clusters must have the same size or be smaller than
 /// the provided size.
 ///
 /// # Arguments
 ///
-/// * `dendrogram` - The dendogram returned by create_dendrogram.
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
 /// * `assignments` - cluster number for each row. This is passed as an argument for you to be able to reuse the vector across multiple calls.
-///    The vector will be resized if too small.
+///   The vector will be resized if too small.
 /// * `max_cluster_size` - The maximum size of the returned clusters.
 ///
 /// # Returns
 ///
 /// The number of clusters found.
 ///
-pub fn assign_rows_to_clusters(
-    dendrogram: &Dendrogram,
+pub fn assign_rows_to_clusters<H: Hierarchy>(
+    dendrogram: H,
     assignments: &mut Vec<usize>,
     max_cluster_size: usize,
 ) -> usize {
-    if dendrogram.size() > assignments.len() {
-        assignments.resize(dendrogram.size(), usize::MAX);
+    assign_rows_splitting(dendrogram, assignments, |node| {
+        dendrogram.size(node) > max_cluster_size
+    })
+}
+
+/// This traverses the dendrogram until it finds clusters of the same size or smaller than the given size,
+/// and returns these clusters.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `max_cluster_size` - The maximum size of the clusters returned.
+///
+/// # Returns
+///
+/// The indices of the rows that belong to each returned cluster.
+///
+pub fn find_clusters<H: Hierarchy>(dendrogram: H, max_cluster_size: usize) -> Vec<Vec<usize>> {
+    let n_rows = dendrogram.size(dendrogram.root());
+    let mut assignments = vec![usize::MAX; n_rows];
+    let num_clusters = assign_rows_to_clusters(dendrogram, &mut assignments, max_cluster_size);
+    group_rows(&assignments, num_clusters)
+}
+
+/// This assigns a cluster number to each row by cutting the dendrogram at the given distance:
+/// the traversal stops at any node whose merge distance is lower than or equal to the threshold.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `assignments` - cluster number for each row. This is passed as an argument for you to be able to reuse the vector across multiple calls.
+///   The vector will be resized if too small.
+/// * `threshold` - The maximum merge distance within the returned clusters.
+///
+/// # Returns
+///
+/// The number of clusters found.
+///
+pub fn assign_rows_to_clusters_by_distance<H: Hierarchy>(
+    dendrogram: H,
+    assignments: &mut Vec<usize>,
+    threshold: f32,
+) -> usize {
+    assign_rows_splitting(dendrogram, assignments, |node| {
+        dendrogram.distance(node) > threshold
+    })
+}
+
+/// This traverses the dendrogram until it finds clusters whose merge distance is lower than or equal to the given threshold,
+/// and returns these clusters.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `threshold` - The maximum merge distance within the returned clusters.
+///
+/// # Returns
+///
+/// The indices of the rows that belong to each returned cluster.
+///
+pub fn find_clusters_by_distance<H: Hierarchy>(dendrogram: H, threshold: f32) -> Vec<Vec<usize>> {
+    let n_rows = dendrogram.size(dendrogram.root());
+    let mut assignments = vec![usize::MAX; n_rows];
+    let num_clusters = assign_rows_to_clusters_by_distance(dendrogram, &mut assignments, threshold);
+    group_rows(&assignments, num_clusters)
+}
+
+/// A candidate node for [`assign_rows_to_k_clusters`], ordered so that the node to split first is the greatest.
+struct Split<N> {
+    node: N,
+    distance: f32,
+    size: usize,
+    order: usize, // discovery order, to break ties deterministically
+}
+
+impl<N> Ord for Split<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.size.cmp(&other.size))
+            .then(other.order.cmp(&self.order))
     }
+}
 
-    let mut cluster_n = 0;
+impl<N> PartialOrd for Split<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<N> PartialEq for Split<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<N> Eq for Split<N> {}
+
+impl<N: Copy> Split<N> {
+    fn new<H: Hierarchy<Node = N>>(tree: H, node: N, order: usize) -> Split<N> {
+        Split {
+            node,
+            distance: tree.distance(node),
+            size: tree.size(node),
+            order,
+        }
+    }
+}
+
+/// This assigns a cluster number to each row so that there are exactly `k` clusters, or as many clusters as rows if there are fewer rows.
+/// The nodes with the highest merge distance are split first. Ties are broken by splitting the largest node first, then the node found first.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `assignments` - cluster number for each row. This is passed as an argument for you to be able to reuse the vector across multiple calls.
+///   The vector will be resized if too small.
+/// * `k` - The number of clusters. 0 is treated as 1.
+///
+/// # Returns
+///
+/// The number of clusters found.
+///
+pub fn assign_rows_to_k_clusters<H: Hierarchy>(
+    dendrogram: H,
+    assignments: &mut Vec<usize>,
+    k: usize,
+) -> usize {
+    let root = dendrogram.root();
+    if dendrogram.size(root) > assignments.len() {
+        assignments.resize(dendrogram.size(root), usize::MAX);
+    }
+
+    // leaves are kept out of the heap: with negative distances, they would come before some nodes
+    let mut heap = BinaryHeap::new();
+    let mut leaves = Vec::new();
+    let mut order = 0;
+    let mut new_nodes = vec![root];
+    loop {
+        for node in new_nodes.drain(..) {
+            let split = Split::new(dendrogram, node, order);
+            order += 1;
+            match dendrogram.children(node) {
+                None => leaves.push(split),
+                Some(_) => heap.push(split),
+            }
+        }
+        if heap.len() + leaves.len() >= k {
+            break;
+        }
+        match heap.pop() {
+            None => break, // only leaves are left
+            Some(split) => {
+                let (cluster1, cluster2) = dendrogram.children(split.node).unwrap();
+                new_nodes.extend([cluster1, cluster2]);
+            }
+        }
+    }
+
+    let mut splits = heap.into_vec();
+    splits.append(&mut leaves);
+    splits.sort_by_key(|split| split.order);
     let mut stack = Vec::new();
+    for (cluster_n, split) in splits.iter().enumerate() {
+        assign_rows_to(cluster_n, dendrogram, split.node, assignments, &mut stack);
+    }
+    splits.len()
+}
+
+/// This splits the dendrogram into exactly `k` clusters, or as many clusters as rows if there are fewer rows,
+/// and returns these clusters. The nodes with the highest merge distance are split first.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `k` - The number of clusters. 0 is treated as 1.
+///
+/// # Returns
+///
+/// The indices of the rows that belong to each returned cluster.
+///
+pub fn find_k_clusters<H: Hierarchy>(dendrogram: H, k: usize) -> Vec<Vec<usize>> {
+    let n_rows = dendrogram.size(dendrogram.root());
+    let mut assignments = vec![usize::MAX; n_rows];
+    let num_clusters = assign_rows_to_k_clusters(dendrogram, &mut assignments, k);
+    group_rows(&assignments, num_clusters)
+}
+
+/// The cluster number given to the rows that don't belong to any cluster.
+pub const OUTLIER: usize = usize::MAX;
+
+/// What [`assign_rows_to_clusters_with_min_size`] does with the clusters smaller than the minimum size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndersizedClusters {
+    /// Their rows are assigned to [`OUTLIER`].
+    Outliers,
+    /// They are merged into the cluster(s) of their sibling in the dendrogram. If the sibling is split further,
+    /// they are merged into its largest part.
+    ///
+    /// The clusters can therefore be larger than `max_cluster_size`: the merged fragments add up to the sibling's
+    /// size, and a node whose two children are both too small is not split at all.
+    MergeIntoSibling,
+}
+
+/// This assigns a cluster number to each row like [`assign_rows_to_clusters`], except that splitting large clusters
+/// doesn't fragment them into many singletons: the parts smaller than `min_cluster_size` become outliers or are merged
+/// into their sibling.
+///
+/// With [`UndersizedClusters::MergeIntoSibling`], a node whose two children are both too small is not split,
+/// even if it is larger than `max_cluster_size`.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `assignments` - cluster number for each row, or [`OUTLIER`]. This is passed as an argument for you to be able to reuse the vector across multiple calls.
+///   The vector will be resized if too small.
+/// * `max_cluster_size` - The maximum size of the clusters before they are split.
+/// * `min_cluster_size` - The minimum size of the returned clusters.
+/// * `undersized` - What to do with the clusters smaller than `min_cluster_size`.
+///
+/// # Returns
+///
+/// The number of clusters found, outliers excluded.
+///
+pub fn assign_rows_to_clusters_with_min_size<H: Hierarchy>(
+    dendrogram: H,
+    assignments: &mut Vec<usize>,
+    max_cluster_size: usize,
+    min_cluster_size: usize,
+    undersized: UndersizedClusters,
+) -> usize {
+    let root = dendrogram.root();
+    if dendrogram.size(root) > assignments.len() {
+        assignments.resize(dendrogram.size(root), usize::MAX);
+    }
+    let merge = undersized == UndersizedClusters::MergeIntoSibling;
+
+    let mut cluster_n = 0;
+    // groups of undersized clusters waiting to be merged into another cluster
+    let mut fragments: Vec<Vec<H::Node>> = Vec::new();
+    let mut stack: Vec<(H::Node, Option<usize>)> = vec![(root, None)];
     let mut same_cluster_stack = Vec::new();
-    stack.push(dendrogram);
 
-    while let Some(current) = stack.pop() {
-        match current {
-            Dendrogram::Leaf(row_index) => {
-                assignments[*row_index] = cluster_n;
-                cluster_n += 1;
+    while let Some((current, group)) = stack.pop() {
+        let children = match dendrogram.children(current) {
+            Some((c1, c2)) if dendrogram.size(current) > max_cluster_size => {
+                let small1 = dendrogram.size(c1) < min_cluster_size;
+                let small2 = dendrogram.size(c2) < min_cluster_size;
+                if merge && small1 && small2 {
+                    None
+                } else {
+                    Some((c1, c2, small1, small2))
+                }
             }
-            Dendrogram::Node(cluster1, cluster2, _, size) => {
-                if *size > max_cluster_size {
-                    // dive deeper
-                    stack.push(cluster1);
-                    stack.push(cluster2);
+            _ => None,
+        };
+
+        match children {
+            Some((c1, c2, small1, small2)) => {
+                if merge && (small1 || small2) {
+                    let (fragment, sibling) = if small1 { (c1, c2) } else { (c2, c1) };
+                    let g = group.unwrap_or_else(|| {
+                        fragments.push(Vec::new());
+                        fragments.len() - 1
+                    });
+                    fragments[g].push(fragment);
+                    stack.push((sibling, Some(g)));
                 } else {
-                    assign_rows_to(cluster_n, &current, assignments, &mut same_cluster_stack);
-                    cluster_n += 1;
+                    // the pending fragments go to the largest child
+                    let (smaller, larger) = if dendrogram.size(c1) < dendrogram.size(c2) {
+                        (c1, c2)
+                    } else {
+                        (c2, c1)
+                    };
+                    stack.push((smaller, None));
+                    stack.push((larger, group));
                 }
             }
+            None => {
+                if !merge && dendrogram.size(current) < min_cluster_size {
+                    assign_rows_to(
+                        OUTLIER,
+                        dendrogram,
+                        current,
+                        assignments,
+                        &mut same_cluster_stack,
+                    );
+                    continue;
+                }
+                assign_rows_to(
+                    cluster_n,
+                    dendrogram,
+                    current,
+                    assignments,
+                    &mut same_cluster_stack,
+                );
+                if let Some(g) = group {
+                    for fragment in &fragments[g] {
+                        assign_rows_to(
+                            cluster_n,
+                            dendrogram,
+                            *fragment,
+                            assignments,
+                            &mut same_cluster_stack,
+                        );
+                    }
+                }
+                cluster_n += 1;
+            }
         }
     }
     cluster_n
 }
 
-/// This traverses the dendrogram until it finds clusters of the same size or smaller than the given size,
-/// and returns these clusters.
+/// This is the same as [`find_clusters`], except that the parts smaller than `min_cluster_size` become outliers
+/// or are merged into their sibling. See [`assign_rows_to_clusters_with_min_size`].
 ///
 /// # Arguments
 ///
-/// * `dendrogram` - The dendogram returned by create_dendrogram.
-/// * `max_cluster_size` - The maximum size of the clusters returned.
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `max_cluster_size` - The maximum size of the clusters before they are split.
+/// * `min_cluster_size` - The minimum size of the returned clusters.
+/// * `undersized` - What to do with the clusters smaller than `min_cluster_size`.
 ///
 /// # Returns
 ///
-/// The indices of the rows that belong to each returned cluster.
+/// The indices of the rows that belong to each returned cluster, and the indices of the outliers.
 ///
-pub fn find_clusters(dendrogram: &Dendrogram, max_cluster_size: usize) -> Vec<Vec<usize>> {
-    let n_rows = dendrogram.size();
-    let mut assignments = vec![usize::MAX as usize; n_rows];
-    let num_clusters = assign_rows_to_clusters(&dendrogram, &mut assignments, max_cluster_size);
+pub fn find_clusters_with_min_size<H: Hierarchy>(
+    dendrogram: H,
+    max_cluster_size: usize,
+    min_cluster_size: usize,
+    undersized: UndersizedClusters,
+) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let n_rows = dendrogram.size(dendrogram.root());
+    let mut assignments = vec![usize::MAX; n_rows];
+    let num_clusters = assign_rows_to_clusters_with_min_size(
+        dendrogram,
+        &mut assignments,
+        max_cluster_size,
+        min_cluster_size,
+        undersized,
+    );
+    group_rows_with_outliers(&assignments, num_clusters)
+}
+
+/// Group the row indices by cluster number, setting the outliers apart.
+pub(crate) fn group_rows_with_outliers(
+    assignments: &[usize],
+    num_clusters: usize,
+) -> (Vec<Vec<usize>>, Vec<usize>) {
     let mut clusters: Vec<Vec<usize>> = (0..num_clusters).map(|_| Vec::new()).collect();
+    let mut outliers = Vec::new();
 
     for (row_idx, assignment) in assignments.iter().enumerate() {
-        clusters[*assignment].push(row_idx)
+        if *assignment == OUTLIER {
+            outliers.push(row_idx);
+        } else {
+            clusters[*assignment].push(row_idx)
+        }
     }
-    return clusters;
+    (clusters, outliers)
 }
@@ -0,0 +1,28 @@
+use crate::error::MonotonicityViolation;
+
+/// What [`crate::create_dendrogram_with_diagnostics`] found out while clustering.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    /// The number of times the distance between two clusters decreased after they were extended.
+    /// If this is not zero, the dendrogram is not a valid complete-linkage dendrogram.
+    pub num_violations: usize,
+    /// The first violations, up to [`Diagnostics::MAX_REPORTED_VIOLATIONS`].
+    pub violations: Vec<MonotonicityViolation>,
+}
+
+impl Diagnostics {
+    /// The maximum number of violations kept in `violations`. All of them are counted in `num_violations`.
+    pub const MAX_REPORTED_VIOLATIONS: usize = 100;
+
+    /// Whether the distance function behaved as required during the whole clustering.
+    pub fn is_valid(&self) -> bool {
+        self.num_violations == 0
+    }
+
+    pub(crate) fn report(&mut self, violation: MonotonicityViolation) {
+        self.num_violations += 1;
+        if self.violations.len() < Self::MAX_REPORTED_VIOLATIONS {
+            self.violations.push(violation);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::dendrogram::{assign_rows_to, group_rows, Hierarchy};
+
+/// Whether a child branch stands out from its parent: it is large enough and it was merged
+/// at a distance sufficiently lower than the parent's merge distance.
+fn stands_out<H: Hierarchy>(
+    dendrogram: H,
+    parent: H::Node,
+    child: H::Node,
+    min_cluster_size: usize,
+    min_relative_gap: f32,
+) -> bool {
+    if dendrogram.size(child) < min_cluster_size {
+        return false;
+    }
+    let parent_distance = dendrogram.distance(parent);
+    if parent_distance <= 0.0 {
+        return false;
+    }
+    (parent_distance - dendrogram.distance(child)) / parent_distance >= min_relative_gap
+}
+
+/// Whether each node, numbered in pre-order, is split by [`assign_rows_to_clusters_dynamically`], along with the
+/// pre-order number of the second child of each node. The first child of node `i` is node `i + 1`.
+fn find_splits<H: Hierarchy>(
+    dendrogram: H,
+    min_cluster_size: usize,
+    min_relative_gap: f32,
+) -> (Vec<bool>, Vec<usize>) {
+    let mut nodes = Vec::new();
+    let mut second_children = Vec::new();
+    let mut stack = vec![(dendrogram.root(), None)];
+    while let Some((current, second_child_of)) = stack.pop() {
+        if let Some(parent) = second_child_of {
+            second_children[parent] = nodes.len();
+        }
+        if let Some((c1, c2)) = dendrogram.children(current) {
+            stack.push((c2, Some(nodes.len())));
+            stack.push((c1, None));
+        }
+        nodes.push(current);
+        second_children.push(0);
+    }
+
+    // children come after their parent in pre-order, so they are visited first in reverse
+    let mut splits = vec![false; nodes.len()];
+    for i in (0..nodes.len()).rev() {
+        if let Some((c1, c2)) = dendrogram.children(nodes[i]) {
+            let (split1, split2) = (splits[i + 1], splits[second_children[i]]);
+            let small1 = dendrogram.size(c1) < min_cluster_size;
+            let small2 = dendrogram.size(c2) < min_cluster_size;
+            splits[i] = match (small1, small2) {
+                (true, true) => false,
+                // the large child stands for the node
+                (true, false) => split2,
+                (false, true) => split1,
+                (false, false) => {
+                    split1
+                        || split2
+                        || (stands_out(
+                            dendrogram,
+                            nodes[i],
+                            c1,
+                            min_cluster_size,
+                            min_relative_gap,
+                        ) && stands_out(
+                            dendrogram,
+                            nodes[i],
+                            c2,
+                            min_cluster_size,
+                            min_relative_gap,
+                        ))
+                }
+            };
+        }
+    }
+    (splits, second_children)
+}
+
+/// This assigns a cluster number to each row with an adaptive cut that follows the merge distances of each branch
+/// instead of a single global threshold, so that dense and loose clusters can both be found.
+///
+/// A node is split when both its children stand out from it, i.e. when they have at least `min_cluster_size` rows
+/// and were merged at a distance at least `min_relative_gap` times lower than the node's distance.
+/// A child that doesn't stand out is not a cluster of its own, but its own children can still stand out from it,
+/// so a node is also split when one of its children is. This way, tight clusters nested in a loose branch are found
+/// even if the loose branch doesn't stand out from its parent.
+/// When one of the children is smaller than `min_cluster_size`, it cannot be a cluster of its own,
+/// so it is kept with its sibling, which stands for the node.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `assignments` - cluster number for each row. This is passed as an argument for you to be able to reuse the vector across multiple calls.
+///   The vector will be resized if too small.
+/// * `min_cluster_size` - The minimum size of a branch to be a cluster.
+/// * `min_relative_gap` - How much lower, relatively to its parent's distance, the distance of a branch must be to stand out.
+///   Between 0 and 1: lower values are treated as 0, and higher values and NaN as 1.
+///
+/// # Returns
+///
+/// The number of clusters found.
+///
+pub fn assign_rows_to_clusters_dynamically<H: Hierarchy>(
+    dendrogram: H,
+    assignments: &mut Vec<usize>,
+    min_cluster_size: usize,
+    min_relative_gap: f32,
+) -> usize {
+    let root = dendrogram.root();
+    if dendrogram.size(root) > assignments.len() {
+        assignments.resize(dendrogram.size(root), usize::MAX);
+    }
+    let min_relative_gap = if min_relative_gap.is_nan() {
+        1.0
+    } else {
+        min_relative_gap.clamp(0.0, 1.0)
+    };
+    let (splits, second_children) = find_splits(dendrogram, min_cluster_size, min_relative_gap);
+
+    let mut cluster_n = 0;
+    // groups of small branches kept with a sibling that might be split later
+    let mut fragments: Vec<Vec<H::Node>> = Vec::new();
+    // the nodes come with their pre-order number
+    let mut stack: Vec<(H::Node, usize, Option<usize>)> = vec![(root, 0, None)];
+    let mut same_cluster_stack = Vec::new();
+
+    while let Some((current, i, group)) = stack.pop() {
+        if let Some((c1, c2)) = dendrogram.children(current) {
+            let (i1, i2) = (i + 1, second_children[i]);
+            let small1 = dendrogram.size(c1) < min_cluster_size;
+            let small2 = dendrogram.size(c2) < min_cluster_size;
+            if small1 != small2 {
+                // the small branch goes along with its sibling, which stands for the current cluster
+                let (fragment, sibling, sibling_i) =
+                    if small1 { (c1, c2, i2) } else { (c2, c1, i1) };
+                let g = group.unwrap_or_else(|| {
+                    fragments.push(Vec::new());
+                    fragments.len() - 1
+                });
+                fragments[g].push(fragment);
+                stack.push((sibling, sibling_i, Some(g)));
+                continue;
+            }
+            if splits[i] {
+                // the pending fragments go to the largest child
+                let ((smaller, smaller_i), (larger, larger_i)) =
+                    if dendrogram.size(c1) < dendrogram.size(c2) {
+                        ((c1, i1), (c2, i2))
+                    } else {
+                        ((c2, i2), (c1, i1))
+                    };
+                stack.push((smaller, smaller_i, None));
+                stack.push((larger, larger_i, group));
+                continue;
+            }
+        }
+
+        assign_rows_to(
+            cluster_n,
+            dendrogram,
+            current,
+            assignments,
+            &mut same_cluster_stack,
+        );
+        if let Some(g) = group {
+            for fragment in &fragments[g] {
+                assign_rows_to(
+                    cluster_n,
+                    dendrogram,
+                    *fragment,
+                    assignments,
+                    &mut same_cluster_stack,
+                );
+            }
+        }
+        cluster_n += 1;
+    }
+    cluster_n
+}
+
+/// This finds clusters with an adaptive cut that follows the merge distances of each branch.
+/// See [`assign_rows_to_clusters_dynamically`].
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `min_cluster_size` - The minimum size of a branch to be a cluster.
+/// * `min_relative_gap` - How much lower, relatively to its parent's distance, the distance of a branch must be to stand out.
+///   Between 0 and 1: lower values are treated as 0, and higher values and NaN as 1.
+///
+/// # Returns
+///
+/// The indices of the rows that belong to each returned cluster.
+///
+pub fn find_clusters_dynamically<H: Hierarchy>(
+    dendrogram: H,
+    min_cluster_size: usize,
+    min_relative_gap: f32,
+) -> Vec<Vec<usize>> {
+    let n_rows = dendrogram.size(dendrogram.root());
+    let mut assignments = vec![usize::MAX; n_rows];
+    let num_clusters = assign_rows_to_clusters_dynamically(
+        dendrogram,
+        &mut assignments,
+        min_cluster_size,
+        min_relative_gap,
+    );
+    group_rows(&assignments, num_clusters)
+}
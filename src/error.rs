@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// Two clusters whose distance decreased after one of them was extended, which breaks the property
+/// `d(x1 U x2, y1 U y2) >= d(x1, y1)` required by the algorithm.
+///
+/// Clusters are identified by the index of the row they were created from. A cluster keeps this index when
+/// another cluster is merged into it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonotonicityViolation {
+    pub cluster1_index: usize,
+    pub cluster2_index: usize,
+    /// The distance before the clusters were extended.
+    pub previous_distance: f32,
+    /// The distance after the clusters were extended. It is smaller than `previous_distance`.
+    pub new_distance: f32,
+}
+
+/// The errors returned by [`crate::try_create_dendrogram`] and the other fallible functions of this crate.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClusteringError {
+    /// The data has no rows.
+    EmptyData,
+    /// The data has no columns.
+    NoColumns,
+    /// A parameter is out of its valid range.
+    InvalidParameter(String),
+    /// The distance function of the cluster summaries does not satisfy the properties required for complete-linkage.
+    MonotonicityViolation(MonotonicityViolation),
+    /// A linkage matrix passed to [`crate::Dendrogram::from_linkage_matrix`] is malformed.
+    InvalidLinkageMatrix(String),
+}
+
+impl fmt::Display for ClusteringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClusteringError::EmptyData => write!(f, "the data has no rows"),
+            ClusteringError::NoColumns => write!(f, "the data has no columns"),
+            ClusteringError::InvalidParameter(message) => write!(f, "{}", message),
+            ClusteringError::MonotonicityViolation(v) => write!(
+                f,
+                "distance function does not statisfy properties required for complete-linkage: the distance between clusters {} and {} went from {} to {}",
+                v.cluster1_index, v.cluster2_index, v.previous_distance, v.new_distance
+            ),
+            ClusteringError::InvalidLinkageMatrix(message) => {
+                write!(f, "invalid linkage matrix: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClusteringError {}
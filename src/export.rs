@@ -0,0 +1,125 @@
+use crate::dendrogram::Dendrogram;
+use std::io::{self, Write};
+
+/// The steps of the non-recursive Newick writer.
+enum NewickStep<'a> {
+    // a subtree to write, with the distance of its parent
+    Open(&'a Dendrogram, Option<f32>),
+    Separator,
+    // the end of a node's children, with the distance of its parent
+    Close(&'a Dendrogram, Option<f32>),
+}
+
+pub(crate) fn row_label(row_index: usize, labels: Option<&dyn Fn(usize) -> String>) -> String {
+    match labels {
+        Some(labels) => labels(row_index),
+        None => row_index.to_string(),
+    }
+}
+
+/// Quote a Newick label if it contains characters with a special meaning.
+fn newick_label(label: &str) -> String {
+    let special = |c: char| c.is_whitespace() || "()[]':;,".contains(c);
+    if label.contains(special) {
+        format!("'{}'", label.replace('\'', "''"))
+    } else {
+        label.to_string()
+    }
+}
+
+fn write_branch_length<W: Write>(
+    writer: &mut W,
+    node: &Dendrogram,
+    parent_distance: Option<f32>,
+) -> io::Result<()> {
+    match parent_distance {
+        Some(d) => write!(writer, ":{}", (d - node.distance()).max(0.0)),
+        None => Ok(()),
+    }
+}
+
+fn dot_label(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Dendrogram {
+    /// Write the dendrogram in the Newick format, e.g. `((0:1,1:1):2,2:3);`.
+    ///
+    /// The length of a branch is the difference between the merge distance of the parent node and the merge distance of the child,
+    /// leaves having a merge distance of 0. Labels that contain Newick special characters are quoted.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the Newick string is written. Wrap it in a `BufWriter` if writes are expensive.
+    /// * `labels` - Gives the label of each leaf from its row index. Row indices are used if `None`.
+    pub fn write_newick<W: Write>(
+        &self,
+        mut writer: W,
+        labels: Option<&dyn Fn(usize) -> String>,
+    ) -> io::Result<()> {
+        let mut stack = vec![NewickStep::Open(self, None)];
+        while let Some(step) = stack.pop() {
+            match step {
+                NewickStep::Open(leaf @ Dendrogram::Leaf(row_index), parent_distance) => {
+                    let label = row_label(*row_index, labels);
+                    write!(writer, "{}", newick_label(&label))?;
+                    write_branch_length(&mut writer, leaf, parent_distance)?;
+                }
+                NewickStep::Open(node @ Dendrogram::Node(c1, c2, distance, _), parent_distance) => {
+                    write!(writer, "(")?;
+                    stack.push(NewickStep::Close(node, parent_distance));
+                    stack.push(NewickStep::Open(c2, Some(*distance)));
+                    stack.push(NewickStep::Separator);
+                    stack.push(NewickStep::Open(c1, Some(*distance)));
+                }
+                NewickStep::Separator => write!(writer, ",")?,
+                NewickStep::Close(node, parent_distance) => {
+                    write!(writer, ")")?;
+                    write_branch_length(&mut writer, node, parent_distance)?;
+                }
+            }
+        }
+        writeln!(writer, ";")
+    }
+
+    /// Write the dendrogram as a Graphviz DOT digraph, with edges going from each node to its children.
+    /// Inner nodes are labelled with their merge distance and their size.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the DOT graph is written. Wrap it in a `BufWriter` if writes are expensive.
+    /// * `labels` - Gives the label of each leaf from its row index. Row indices are used if `None`.
+    pub fn write_dot<W: Write>(
+        &self,
+        mut writer: W,
+        labels: Option<&dyn Fn(usize) -> String>,
+    ) -> io::Result<()> {
+        writeln!(writer, "digraph dendrogram {{")?;
+        let mut next_id = 0;
+        let mut stack: Vec<(&Dendrogram, Option<usize>)> = vec![(self, None)];
+        while let Some((current, parent_id)) = stack.pop() {
+            let id = next_id;
+            next_id += 1;
+            match current {
+                Dendrogram::Leaf(row_index) => {
+                    let label = row_label(*row_index, labels);
+                    writeln!(
+                        writer,
+                        "  {} [label=\"{}\", shape=box];",
+                        id,
+                        dot_label(&label)
+                    )?;
+                }
+                Dendrogram::Node(c1, c2, distance, size) => {
+                    writeln!(writer, "  {} [label=\"d={}\\nn={}\"];", id, distance, size)?;
+                    stack.push((c2, Some(id)));
+                    stack.push((c1, Some(id)));
+                }
+            }
+            if let Some(parent_id) = parent_id {
+                writeln!(writer, "  {} -> {};", parent_id, id)?;
+            }
+        }
+        writeln!(writer, "}}")
+    }
+}
@@ -0,0 +1,385 @@
+use crate::bitset::{check_row, infer_cardinalities, BitsetSchema, BitsetSummary};
+use crate::data::{ClusterSummary, TypedClusterSummary, TypedIndexableData};
+use crate::error::ClusteringError;
+#[cfg(feature = "roaring")]
+use roaring::RoaringBitmap;
+use smallvec::SmallVec;
+use std::any::Any;
+
+/// The default cardinality up to which a column is stored as inline bits.
+pub const DEFAULT_MAX_INLINE_CARDINALITY: usize = 64;
+
+/// Categories must be lower than this, 2^24, which is the largest range of integers that `f32` values represent
+/// exactly. Beyond it, [`HybridData`] would give the same value to distinct categories, and the sorted projection
+/// would interleave them.
+pub const MAX_CATEGORY: usize = 1 << 24;
+
+/// The length beyond which a sorted set of categories is upgraded to a roaring bitmap.
+#[cfg(feature = "roaring")]
+const MAX_SORTED_LEN: usize = 512;
+
+/// Where the categories of a column are stored.
+#[derive(Debug, Clone, PartialEq)]
+enum Layout {
+    // the column's index in the bitset schema
+    Inline(usize),
+    // the index of the column's set
+    Sparse(usize),
+}
+
+/// The layout of categorical columns into a [`HybridSummary`]: low-cardinality columns share a bitset,
+/// like in a [`BitsetSchema`], and every high-cardinality column gets its own set of categories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HybridSchema {
+    cardinalities: Vec<usize>,
+    layouts: Vec<Layout>,
+    inline: BitsetSchema,
+    num_sets: usize,
+}
+
+impl HybridSchema {
+    /// Same as [`HybridSchema::with_max_inline_cardinality`] with [`DEFAULT_MAX_INLINE_CARDINALITY`].
+    pub fn new(cardinalities: &[usize]) -> HybridSchema {
+        HybridSchema::with_max_inline_cardinality(cardinalities, DEFAULT_MAX_INLINE_CARDINALITY)
+    }
+
+    /// # Arguments
+    ///
+    /// * `cardinalities` - The number of categories of each column. Categories are coded from 0 to cardinality - 1.
+    /// * `max_inline_cardinality` - The cardinality up to which a column is stored as inline bits.
+    pub fn with_max_inline_cardinality(
+        cardinalities: &[usize],
+        max_inline_cardinality: usize,
+    ) -> HybridSchema {
+        let mut layouts = Vec::with_capacity(cardinalities.len());
+        let mut inline_cardinalities = Vec::new();
+        let mut num_sets = 0;
+        for cardinality in cardinalities {
+            if *cardinality <= max_inline_cardinality {
+                layouts.push(Layout::Inline(inline_cardinalities.len()));
+                inline_cardinalities.push(*cardinality);
+            } else {
+                layouts.push(Layout::Sparse(num_sets));
+                num_sets += 1;
+            }
+        }
+        HybridSchema {
+            cardinalities: cardinalities.to_vec(),
+            layouts,
+            inline: BitsetSchema::new(&inline_cardinalities),
+            num_sets,
+        }
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.cardinalities.len()
+    }
+
+    pub fn cardinality(&self, column_index: usize) -> usize {
+        self.cardinalities[column_index]
+    }
+
+    /// Whether the column is stored as inline bits rather than as a set of categories.
+    pub fn is_inline(&self, column_index: usize) -> bool {
+        matches!(self.layouts[column_index], Layout::Inline(_))
+    }
+
+    /// Check the number of columns of a row and its categories, which must also be lower than [`MAX_CATEGORY`].
+    fn check(&self, categories: &[usize]) -> Result<(), String> {
+        check_row(&self.cardinalities, categories)?;
+        match categories.iter().find(|c| **c >= MAX_CATEGORY) {
+            Some(category) => Err(format!(
+                "category {} is not lower than {}",
+                category, MAX_CATEGORY
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The summary of a row, given the category of each column.
+    ///
+    /// # Errors
+    ///
+    /// [`ClusteringError::InvalidParameter`] if the row doesn't have as many columns as the schema,
+    /// if a category is out of its column's cardinality, or if it isn't lower than [`MAX_CATEGORY`].
+    pub fn summarize(&self, categories: &[usize]) -> Result<HybridSummary, ClusteringError> {
+        self.check(categories)
+            .map_err(ClusteringError::InvalidParameter)?;
+        Ok(self.summarize_unchecked(categories))
+    }
+
+    /// Same as [`HybridSchema::summarize`], for rows that have already been checked.
+    pub(crate) fn summarize_unchecked(&self, categories: &[usize]) -> HybridSummary {
+        let mut summary = HybridSummary {
+            bits: self.inline.empty_summary(),
+            sets: vec![CategorySet::default(); self.num_sets],
+        };
+        for (layout, category) in self.layouts.iter().zip(categories) {
+            match layout {
+                Layout::Inline(c) => summary.bits.insert(self.inline.bit(*c, *category)),
+                Layout::Sparse(s) => summary.sets[*s] = CategorySet::single(*category as u32),
+            }
+        }
+        summary
+    }
+}
+
+/// The categories of a high-cardinality column.
+#[derive(Debug, Clone)]
+pub(crate) enum CategorySet {
+    // a single row's category is stored inline
+    Sorted(SmallVec<[u32; 4]>),
+    #[cfg(feature = "roaring")]
+    Roaring(RoaringBitmap),
+}
+
+impl Default for CategorySet {
+    fn default() -> Self {
+        CategorySet::Sorted(SmallVec::new())
+    }
+}
+
+impl CategorySet {
+    fn single(category: u32) -> CategorySet {
+        let mut categories = SmallVec::new();
+        categories.push(category);
+        CategorySet::Sorted(categories)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            CategorySet::Sorted(categories) => categories.len(),
+            #[cfg(feature = "roaring")]
+            CategorySet::Roaring(bitmap) => bitmap.len() as usize,
+        }
+    }
+
+    pub(crate) fn intersection_len(&self, other: &CategorySet) -> usize {
+        match (self, other) {
+            (CategorySet::Sorted(a), CategorySet::Sorted(b)) => {
+                let (mut i, mut j, mut count) = (0, 0, 0);
+                while i < a.len() && j < b.len() {
+                    match a[i].cmp(&b[j]) {
+                        std::cmp::Ordering::Less => i += 1,
+                        std::cmp::Ordering::Greater => j += 1,
+                        std::cmp::Ordering::Equal => {
+                            count += 1;
+                            i += 1;
+                            j += 1;
+                        }
+                    }
+                }
+                count
+            }
+            #[cfg(feature = "roaring")]
+            (CategorySet::Sorted(a), CategorySet::Roaring(b))
+            | (CategorySet::Roaring(b), CategorySet::Sorted(a)) => {
+                a.iter().filter(|category| b.contains(**category)).count()
+            }
+            #[cfg(feature = "roaring")]
+            (CategorySet::Roaring(a), CategorySet::Roaring(b)) => a.intersection_len(b) as usize,
+        }
+    }
+
+    pub(crate) fn extend(&mut self, other: &CategorySet) {
+        match (&mut *self, other) {
+            (CategorySet::Sorted(a), CategorySet::Sorted(b)) => {
+                let mut union = SmallVec::with_capacity(a.len() + b.len());
+                let (mut i, mut j) = (0, 0);
+                while i < a.len() && j < b.len() {
+                    match a[i].cmp(&b[j]) {
+                        std::cmp::Ordering::Less => {
+                            union.push(a[i]);
+                            i += 1;
+                        }
+                        std::cmp::Ordering::Greater => {
+                            union.push(b[j]);
+                            j += 1;
+                        }
+                        std::cmp::Ordering::Equal => {
+                            union.push(a[i]);
+                            i += 1;
+                            j += 1;
+                        }
+                    }
+                }
+                union.extend_from_slice(&a[i..]);
+                union.extend_from_slice(&b[j..]);
+                *a = union;
+            }
+            #[cfg(feature = "roaring")]
+            (CategorySet::Sorted(a), CategorySet::Roaring(b)) => {
+                let mut bitmap = b.clone();
+                bitmap.extend(a.iter().copied());
+                *self = CategorySet::Roaring(bitmap);
+            }
+            #[cfg(feature = "roaring")]
+            (CategorySet::Roaring(a), CategorySet::Sorted(b)) => a.extend(b.iter().copied()),
+            #[cfg(feature = "roaring")]
+            (CategorySet::Roaring(a), CategorySet::Roaring(b)) => *a |= b,
+        }
+
+        #[cfg(feature = "roaring")]
+        if let CategorySet::Sorted(categories) = self {
+            if categories.len() > MAX_SORTED_LEN {
+                *self = CategorySet::Roaring(categories.iter().copied().collect());
+            }
+        }
+    }
+}
+
+/// A cluster summary that picks a data structure per column: low-cardinality columns are stored as inline bits,
+/// and high-cardinality columns as sorted sets of categories, upgraded to roaring bitmaps as they grow
+/// if the `roaring` feature is enabled.
+///
+/// The distance is the recommended `|A| + |B| - |A ∩ B|`, summed over the columns, whichever their data structure.
+#[derive(Debug, Clone)]
+pub struct HybridSummary {
+    bits: BitsetSummary,
+    sets: Vec<CategorySet>,
+}
+
+impl HybridSummary {
+    /// The number of categories in the cluster.
+    pub fn summary_size(&self) -> usize {
+        self.bits.summary_size() + self.sets.iter().map(|set| set.len()).sum::<usize>()
+    }
+
+    pub fn distance(&self, other: &HybridSummary) -> f32 {
+        let mut distance = self.bits.distance(&other.bits);
+        for (a, b) in self.sets.iter().zip(&other.sets) {
+            distance += (a.len() + b.len() - a.intersection_len(b)) as f32;
+        }
+        distance
+    }
+
+    pub fn extend(&mut self, other: &HybridSummary) {
+        self.bits.extend(&other.bits);
+        for (a, b) in self.sets.iter_mut().zip(&other.sets) {
+            a.extend(b);
+        }
+    }
+}
+
+impl ClusterSummary for HybridSummary {
+    fn summary_size(&self) -> usize {
+        HybridSummary::summary_size(self)
+    }
+
+    fn distance(&self, other: &dyn ClusterSummary) -> f32 {
+        let other = other.as_any().downcast_ref::<HybridSummary>().unwrap();
+        HybridSummary::distance(self, other)
+    }
+
+    fn extend(&mut self, other: &dyn ClusterSummary) {
+        let other = other.as_any().downcast_ref::<HybridSummary>().unwrap();
+        HybridSummary::extend(self, other)
+    }
+
+    fn clear(&mut self) {
+        ClusterSummary::clear(&mut self.bits);
+        self.sets = Vec::new();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl TypedClusterSummary for HybridSummary {
+    fn summary_size(&self) -> usize {
+        HybridSummary::summary_size(self)
+    }
+
+    fn distance(&self, other: &HybridSummary) -> f32 {
+        HybridSummary::distance(self, other)
+    }
+
+    fn extend(&mut self, other: &HybridSummary) {
+        HybridSummary::extend(self, other)
+    }
+
+    fn clear(&mut self) {
+        ClusterSummary::clear(self)
+    }
+}
+
+/// Rows of integer-coded categories, ready to be clustered with [`HybridSummary`] summaries.
+pub struct HybridData {
+    schema: HybridSchema,
+    // row-major categories
+    categories: Vec<usize>,
+    // stored rather than derived from the categories, which are empty when there are no columns
+    num_rows: usize,
+}
+
+impl HybridData {
+    /// Wrap the rows, inferring the cardinality of each column from its largest category.
+    ///
+    /// # Errors
+    ///
+    /// [`ClusteringError::InvalidParameter`] if the rows don't all have the same number of columns,
+    /// or if a category isn't lower than [`MAX_CATEGORY`].
+    pub fn new(rows: &[Vec<usize>]) -> Result<HybridData, ClusteringError> {
+        HybridData::with_schema(HybridSchema::new(&infer_cardinalities(rows)), rows)
+    }
+
+    /// Wrap the rows with a given schema.
+    ///
+    /// # Errors
+    ///
+    /// [`ClusteringError::InvalidParameter`] if a row doesn't have as many columns as the schema,
+    /// if a category is out of its column's cardinality, or if it isn't lower than [`MAX_CATEGORY`].
+    pub fn with_schema(
+        schema: HybridSchema,
+        rows: &[Vec<usize>],
+    ) -> Result<HybridData, ClusteringError> {
+        let mut categories = Vec::with_capacity(rows.len() * schema.num_columns());
+        for (r, row) in rows.iter().enumerate() {
+            schema
+                .check(row)
+                .map_err(|e| ClusteringError::InvalidParameter(format!("row {}: {}", r, e)))?;
+            categories.extend_from_slice(row);
+        }
+        Ok(HybridData {
+            schema,
+            categories,
+            num_rows: rows.len(),
+        })
+    }
+
+    pub fn schema(&self) -> &HybridSchema {
+        &self.schema
+    }
+
+    fn row(&self, row_index: usize) -> &[usize] {
+        let num_columns = self.schema.num_columns();
+        &self.categories[row_index * num_columns..(row_index + 1) * num_columns]
+    }
+}
+
+impl TypedIndexableData for HybridData {
+    type Summary = HybridSummary;
+
+    fn value(&self, row_index: usize, column_index: usize) -> f32 {
+        self.row(row_index)[column_index] as f32
+    }
+
+    fn num_columns(&self) -> usize {
+        self.schema.num_columns()
+    }
+
+    fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    fn create_summary(&self, row_index: usize) -> HybridSummary {
+        self.schema.summarize_unchecked(self.row(row_index))
+    }
+
+    fn tokens(&self, row_index: usize, tokens: &mut Vec<u64>) {
+        for (c, category) in self.row(row_index).iter().enumerate() {
+            tokens.push(((c as u64) << 32) | *category as u64);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use crate::compact::CompactDendrogram;
+use crate::dendrogram::{Dendrogram, Hierarchy};
+
+/// A child of an inner node: a leaf, or the index of an inner node in pre-order.
+#[derive(Clone, Copy)]
+enum Child {
+    Leaf,
+    Inner(usize),
+}
+
+/// An inner node, whose leaves occupy the positions `start..start + size` in the original leaf order,
+/// the first `left_size` ones belonging to its first child.
+struct Inner<N> {
+    node: N,
+    start: usize,
+    left_size: usize,
+    size: usize,
+    children: [Child; 2],
+}
+
+impl<N> Inner<N> {
+    /// The positions of the leaves of the first or the second child.
+    fn child_range(&self, second: bool) -> std::ops::Range<usize> {
+        if second {
+            self.start + self.left_size..self.start + self.size
+        } else {
+            self.start..self.start + self.left_size
+        }
+    }
+}
+
+/// A node waiting to be numbered, with the index of its parent and its slot among the parent's children.
+type Pending<N> = (N, Option<(usize, usize)>);
+
+/// The result of the optimal leaf ordering: which inner nodes, in pre-order, must have their children swapped,
+/// and the resulting order of the rows.
+struct LeafOrdering<N> {
+    nodes: Vec<N>,
+    flips: Vec<bool>,
+    order: Vec<usize>,
+}
+
+/// The positions that can be at the inner end of a child whose outer end is at position `p`:
+/// the leaves of the grandchild that doesn't contain `p`, or `p` itself if the child is a leaf.
+fn inner_ends<N>(inners: &[Inner<N>], child: Child, p: usize) -> std::ops::Range<usize> {
+    match child {
+        Child::Leaf => p..p + 1,
+        Child::Inner(idx) => {
+            let inner = &inners[idx];
+            let in_first = p < inner.start + inner.left_size;
+            inner.child_range(in_first)
+        }
+    }
+}
+
+/// Compute the optimal leaf ordering of Bar-Joseph et al., which minimizes the sum of the distances between adjacent leaves
+/// among the `2^(n-1)` orderings obtained by swapping children.
+fn order_leaves<H, F>(dendrogram: H, distance: F) -> LeafOrdering<H::Node>
+where
+    H: Hierarchy,
+    F: Fn(usize, usize) -> f32,
+{
+    let root = dendrogram.root();
+    let num_rows = dendrogram.size(root);
+
+    // number the inner nodes in pre-order and the leaves from left to right
+    let mut inners: Vec<Inner<H::Node>> = Vec::new();
+    let mut rows: Vec<usize> = Vec::with_capacity(num_rows);
+    let mut stack: Vec<Pending<H::Node>> = vec![(root, None)];
+    while let Some((current, parent)) = stack.pop() {
+        let child = match dendrogram.children(current) {
+            None => {
+                rows.push(dendrogram.row_index(current).unwrap());
+                Child::Leaf
+            }
+            Some((cluster1, cluster2)) => {
+                let idx = inners.len();
+                inners.push(Inner {
+                    node: current,
+                    start: rows.len(),
+                    left_size: dendrogram.size(cluster1),
+                    size: dendrogram.size(current),
+                    children: [Child::Leaf, Child::Leaf],
+                });
+                stack.push((cluster2, Some((idx, 1))));
+                stack.push((cluster1, Some((idx, 0))));
+                Child::Inner(idx)
+            }
+        };
+        if let Some((parent_idx, slot)) = parent {
+            inners[parent_idx].children[slot] = child;
+        }
+    }
+
+    // cost[p * n + q] is the minimum cost of the subtree of the LCA of positions p and q,
+    // when it is ordered from p to q. Children come after their parents in pre-order.
+    let n = num_rows;
+    let mut cost = vec![0.0f32; n * n];
+    let mut distances: Vec<f32> = Vec::new();
+    let mut best_through: Vec<f32> = Vec::new();
+    for inner in inners.iter().rev() {
+        let left = inner.child_range(false);
+        let right = inner.child_range(true);
+        let right_size = right.len();
+        distances.clear();
+        for k in left.clone() {
+            distances.extend(right.clone().map(|m| distance(rows[k], rows[m])));
+        }
+
+        for i in left.clone() {
+            // the best cost of going from i to each m of the right child
+            best_through.clear();
+            best_through.resize(right_size, f32::INFINITY);
+            for k in inner_ends(&inners, inner.children[0], i) {
+                let cost_ik = cost[i * n + k];
+                let row = &distances[(k - left.start) * right_size..][..right_size];
+                for (best, d) in best_through.iter_mut().zip(row) {
+                    *best = best.min(cost_ik + d);
+                }
+            }
+            for j in right.clone() {
+                let mut best = f32::INFINITY;
+                for m in inner_ends(&inners, inner.children[1], j) {
+                    best = best.min(best_through[m - right.start] + cost[m * n + j]);
+                }
+                cost[i * n + j] = best;
+                cost[j * n + i] = best;
+            }
+        }
+    }
+
+    // walk down from the root, choosing the ends of each subtree and swapping the children accordingly
+    let mut flips = vec![false; inners.len()];
+    let mut order = Vec::with_capacity(num_rows);
+    if let Some(root_inner) = inners.first() {
+        let mut best = (f32::INFINITY, 0, 0);
+        for i in root_inner.child_range(false) {
+            for j in root_inner.child_range(true) {
+                if cost[i * n + j] < best.0 {
+                    best = (cost[i * n + j], i, j);
+                }
+            }
+        }
+        let mut stack = vec![(Child::Inner(0), best.1, best.2)];
+        while let Some((child, a, b)) = stack.pop() {
+            let idx = match child {
+                Child::Leaf => {
+                    order.push(rows[a]);
+                    continue;
+                }
+                Child::Inner(idx) => idx,
+            };
+            let inner = &inners[idx];
+            let flip = !inner.child_range(false).contains(&a);
+            flips[idx] = flip;
+            let (first, second) = if flip {
+                (inner.children[1], inner.children[0])
+            } else {
+                (inner.children[0], inner.children[1])
+            };
+
+            let mut best = (f32::INFINITY, a, b);
+            for k in inner_ends(&inners, first, a) {
+                for m in inner_ends(&inners, second, b) {
+                    let c = cost[a * n + k] + distance(rows[k], rows[m]) + cost[m * n + b];
+                    if c < best.0 {
+                        best = (c, k, m);
+                    }
+                }
+            }
+            stack.push((second, best.2, b));
+            stack.push((first, a, best.1));
+        }
+    } else {
+        order.extend_from_slice(&rows);
+    }
+
+    LeafOrdering {
+        nodes: inners.into_iter().map(|inner| inner.node).collect(),
+        flips,
+        order,
+    }
+}
+
+/// This computes the optimal leaf ordering of the dendrogram, i.e. the order of the rows that minimizes the sum of the distances
+/// between adjacent rows, among the orders obtained by swapping the children of the nodes. This makes plots and heatmaps
+/// much easier to read.
+///
+/// This takes `O(n^3)` time and `O(n^2)` memory, so it is meant for dendrograms of up to a few thousand rows.
+///
+/// # Arguments
+///
+/// * `dendrogram` - The dendogram returned by create_dendrogram or create_compact_dendrogram.
+/// * `distance` - The distance between two rows.
+///
+/// # Returns
+///
+/// The row indices from left to right. Use [`Dendrogram::optimize_leaf_order`] to reorder the dendrogram itself.
+pub fn optimal_leaf_order<H, F>(dendrogram: H, distance: F) -> Vec<usize>
+where
+    H: Hierarchy,
+    F: Fn(usize, usize) -> f32,
+{
+    order_leaves(dendrogram, distance).order
+}
+
+impl Dendrogram {
+    /// Swap the children of the nodes so that the leaves follow the optimal leaf ordering. See [`optimal_leaf_order`].
+    pub fn optimize_leaf_order<F: Fn(usize, usize) -> f32>(&mut self, distance: F) {
+        let flips = order_leaves(&*self, distance).flips;
+
+        // same pre-order as order_leaves, first children first
+        let mut next = 0;
+        let mut stack: Vec<&mut Dendrogram> = vec![self];
+        while let Some(current) = stack.pop() {
+            if let Dendrogram::Node(cluster1, cluster2, _, _) = current {
+                if flips[next] {
+                    std::mem::swap(cluster1, cluster2);
+                    stack.push(cluster1);
+                    stack.push(cluster2);
+                } else {
+                    stack.push(cluster2);
+                    stack.push(cluster1);
+                }
+                next += 1;
+            }
+        }
+    }
+}
+
+impl CompactDendrogram {
+    /// Swap the children of the nodes so that the leaves follow the optimal leaf ordering. See [`optimal_leaf_order`].
+    pub fn optimize_leaf_order<F: Fn(usize, usize) -> f32>(&mut self, distance: F) {
+        let ordering = order_leaves(&*self, distance);
+        for (node, flip) in ordering.nodes.into_iter().zip(ordering.flips) {
+            if flip {
+                self.swap_children(node);
+            }
+        }
+    }
+}
@@ -4,15 +4,82 @@
 //! Explanation and examples [here](https://github.com/rom1mouret/catclustering).
 
 mod algorithm;
+mod bitset;
+mod builder;
+mod check;
 mod cluster;
+mod compact;
+mod condensed;
+mod cophenetic;
 mod data;
 mod dendrogram;
+mod diagnostics;
+mod dynamic_cut;
+mod error;
+mod export;
+mod hybrid;
+mod leaf_ordering;
+mod linkage;
+mod neighbors;
+mod render;
+#[cfg(feature = "serde")]
+mod serialization;
+mod sort_keys;
 
+pub use algorithm::create_compact_dendrogram;
 pub use algorithm::create_dendrogram;
+pub use algorithm::create_dendrogram_with_diagnostics;
+pub use algorithm::try_create_compact_dendrogram;
+pub use algorithm::try_create_dendrogram;
+pub use bitset::BitsetData;
+pub use bitset::BitsetSchema;
+pub use bitset::BitsetSummary;
+pub use check::check_summary;
+pub use check::SummaryReport;
+pub use check::SummaryViolation;
+pub use compact::CompactDendrogram;
+pub use condensed::assign_rows_to_stable_clusters;
+pub use condensed::find_stable_clusters;
+pub use condensed::CondensedCluster;
+pub use condensed::CondensedTree;
+pub use cophenetic::cophenetic_correlation;
+pub use cophenetic::CopheneticIndex;
 pub use data::ClusterSummary;
 pub use data::IndexableData;
+pub use data::MaybeSendSync;
+pub use data::TypedClusterSummary;
+pub use data::TypedIndexableData;
 pub use dendrogram::assign_rows_to_clusters;
+pub use dendrogram::assign_rows_to_clusters_by_distance;
+pub use dendrogram::assign_rows_to_clusters_with_min_size;
+pub use dendrogram::assign_rows_to_k_clusters;
 pub use dendrogram::find_clusters;
+pub use dendrogram::find_clusters_by_distance;
+pub use dendrogram::find_clusters_with_min_size;
+pub use dendrogram::find_k_clusters;
+pub use dendrogram::Dendrogram;
+pub use dendrogram::Hierarchy;
+pub use dendrogram::Leaves;
+pub use dendrogram::PostOrder;
+pub use dendrogram::PreOrder;
+pub use dendrogram::UndersizedClusters;
+pub use dendrogram::OUTLIER;
+pub use diagnostics::Diagnostics;
+pub use dynamic_cut::assign_rows_to_clusters_dynamically;
+pub use dynamic_cut::find_clusters_dynamically;
+pub use error::ClusteringError;
+pub use error::MonotonicityViolation;
+pub use hybrid::HybridData;
+pub use hybrid::HybridSchema;
+pub use hybrid::HybridSummary;
+pub use hybrid::DEFAULT_MAX_INLINE_CARDINALITY;
+pub use hybrid::MAX_CATEGORY;
+pub use leaf_ordering::optimal_leaf_order;
+pub use neighbors::AllPairs;
+pub use neighbors::MinHash;
+pub use neighbors::NeighborFinder;
+pub use neighbors::SortedProjection;
+pub use render::RenderOptions;
 
 #[cfg(test)]
 mod tests {
@@ -83,7 +150,15 @@ mod tests {
         cols: usize,
         range: std::ops::Range<i32>,
     ) -> Vec<Vec<i32>> {
-        let mut rng = rand::thread_rng();
+        create_seeded_matrix(&mut rand::thread_rng(), rows, cols, range)
+    }
+
+    fn create_seeded_matrix<R: Rng>(
+        rng: &mut R,
+        rows: usize,
+        cols: usize,
+        range: std::ops::Range<i32>,
+    ) -> Vec<Vec<i32>> {
         let mut matrix = Vec::with_capacity(rows);
 
         for _ in 0..rows {
@@ -114,7 +189,7 @@ mod tests {
             let dendro = create_dendrogram(&matrix, None, &mut rng);
             let clusters = dendrogram::find_clusters(&dendro, n_rows / n_clusters);
 
-            let clustered_rows = clusters.iter().map(|v| v.len()).sum();
+            let clustered_rows = clusters.iter().map(|v| v.len()).sum::<usize>();
             assert!(n_rows == clustered_rows);
 
             assert!(clusters.len() == n_clusters);
@@ -157,4 +232,1351 @@ mod tests {
         assert!(*m2.iter().min().unwrap() == cluster_size);
         assert!(*m2.iter().max().unwrap() == 2 * cluster_size - 1);
     }
+
+    #[test]
+    fn test_sorted_projection_total_order() {
+        use rand::seq::SliceRandom;
+
+        let num_rows = 100;
+        let mut rng = rand::thread_rng();
+        let mut matrix: Vec<Vec<i32>> = (0..num_rows as i32)
+            .map(|v| vec![v, (37 * v) % num_rows as i32])
+            .collect();
+        matrix.shuffle(&mut rng);
+
+        // the values of each column are distinct, so each sort orders the rows by the column that comes first
+        // in the shuffled column order, and only the rows holding consecutive values in that column are linked
+        let chains: Vec<HashSet<(usize, usize)>> = (0..2)
+            .map(|c| {
+                let mut row_of_value = vec![0; num_rows];
+                for (i, row) in matrix.iter().enumerate() {
+                    row_of_value[row[c] as usize] = i;
+                }
+                row_of_value
+                    .windows(2)
+                    .map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1])))
+                    .collect()
+            })
+            .collect();
+        let both: HashSet<(usize, usize)> = chains[0].union(&chains[1]).cloned().collect();
+
+        let neighbors = SortedProjection::default()
+            .find_neighbors(&matrix, &mut rng)
+            .unwrap();
+        assert!(neighbors == chains[0] || neighbors == chains[1] || neighbors == both);
+    }
+
+    fn summarize(
+        matrix: &Vec<Vec<i32>>,
+        dendro: &dendrogram::Dendrogram,
+    ) -> Box<dyn ClusterSummary> {
+        let mut summary: Option<Box<dyn ClusterSummary>> = None;
+        let mut stack = vec![dendro];
+        while let Some(node) = stack.pop() {
+            match node {
+                dendrogram::Dendrogram::Leaf(row_index) => {
+                    let row_summary = matrix.create_cluster_summary(*row_index);
+                    match &mut summary {
+                        None => summary = Some(row_summary),
+                        Some(summary) => ClusterSummary::extend(&mut **summary, &*row_summary),
+                    }
+                }
+                dendrogram::Dendrogram::Node(cluster1, cluster2, _, _) => {
+                    stack.push(cluster1);
+                    stack.push(cluster2);
+                }
+            }
+        }
+        summary.unwrap()
+    }
+
+    #[test]
+    fn test_merge_distances() {
+        let matrix = create_random_matrix(300, 3, 0..4);
+
+        let mut rng = rand::thread_rng();
+        let dendro = create_dendrogram(&matrix, None, &mut rng);
+
+        // every cluster is merged at the distance between its two children
+        let mut stack = vec![&dendro];
+        while let Some(node) = stack.pop() {
+            if let dendrogram::Dendrogram::Node(cluster1, cluster2, distance, _) = node {
+                let summary1 = summarize(&matrix, cluster1);
+                let summary2 = summarize(&matrix, cluster2);
+                assert!(ClusterSummary::distance(&*summary1, &*summary2) == *distance);
+                stack.push(cluster1);
+                stack.push(cluster2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_link_order() {
+        let mut heap = std::collections::BinaryHeap::new();
+        for distance in [3.0, f32::NAN, 1.0, 2.0] {
+            heap.push(cluster::Link {
+                cluster1_index: 0,
+                cluster2_index: 1,
+                cluster1_summary_size: 1,
+                cluster2_summary_size: 1,
+                distance,
+            });
+        }
+
+        // the closest clusters come first, and NaN distances last
+        let distances: Vec<f32> = std::iter::from_fn(|| heap.pop())
+            .map(|link| link.distance)
+            .collect();
+        assert!(distances[..3] == [1.0, 2.0, 3.0]);
+        assert!(distances[3].is_nan());
+    }
+
+    struct HalvesFinder {
+        half: usize,
+    }
+
+    impl NeighborFinder for HalvesFinder {
+        fn find_neighbors<D, R>(
+            &self,
+            _data: &D,
+            _rng: &mut R,
+        ) -> Result<HashSet<(usize, usize)>, ClusteringError>
+        where
+            D: TypedIndexableData,
+            R: rand::RngCore,
+        {
+            // two disconnected chains
+            Ok((0..2 * self.half - 1)
+                .filter(|i| i + 1 != self.half)
+                .map(|i| (i, i + 1))
+                .collect())
+        }
+    }
+
+    struct PairsFinder {
+        pairs: Vec<(usize, usize)>,
+    }
+
+    impl NeighborFinder for PairsFinder {
+        fn find_neighbors<D, R>(
+            &self,
+            _data: &D,
+            _rng: &mut R,
+        ) -> Result<HashSet<(usize, usize)>, ClusteringError>
+        where
+            D: TypedIndexableData,
+            R: rand::RngCore,
+        {
+            Ok(self.pairs.iter().copied().collect())
+        }
+    }
+
+    #[test]
+    fn test_custom_finder_disconnected() {
+        let cluster_size = 50;
+        let mut matrix = create_random_matrix(cluster_size, 3, 0..4);
+        matrix.extend(create_random_matrix(cluster_size, 3, 5..10));
+
+        let mut rng = rand::thread_rng();
+        let finder = HalvesFinder { half: cluster_size };
+        let dendro = create_dendrogram(&matrix, &finder, &mut rng);
+        assert!(dendro.size() == 2 * cluster_size);
+
+        let mut clusters = dendrogram::find_clusters(&dendro, cluster_size);
+        clusters.sort();
+        assert!(clusters.len() == 2);
+        assert!(clusters[0] == (0..cluster_size).collect::<Vec<usize>>());
+        assert!(clusters[1] == (cluster_size..2 * cluster_size).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_minhash_two_clusters() {
+        let cluster_size = 100;
+        let mut matrix = create_random_matrix(cluster_size, 3, 0..4);
+        matrix.extend(create_random_matrix(cluster_size, 3, 5..10));
+
+        let mut rng = rand::thread_rng();
+        let finder = MinHash::default();
+
+        // the two blobs share no category, so their rows never collide
+        let pairs = finder.find_neighbors(&matrix, &mut rng).unwrap();
+        assert!(!pairs.is_empty());
+        assert!(pairs
+            .iter()
+            .all(|(i, j)| i < j && (*i < cluster_size) == (*j < cluster_size)));
+
+        // rows with identical tokens fall in the same buckets, which are chained
+        let num_copies = 3;
+        let duplicated: Vec<Vec<i32>> = (0..num_copies * cluster_size)
+            .map(|i| matrix[i % cluster_size].clone())
+            .collect();
+        let pairs = finder.find_neighbors(&duplicated, &mut rng).unwrap();
+        let mut components: Vec<usize> = (0..duplicated.len()).collect();
+        fn find(components: &mut [usize], mut i: usize) -> usize {
+            while components[i] != i {
+                i = components[i];
+            }
+            i
+        }
+        for (i, j) in pairs {
+            let (root1, root2) = (find(&mut components, i), find(&mut components, j));
+            components[root1] = root2;
+        }
+        for i in cluster_size..duplicated.len() {
+            let original = i % cluster_size;
+            assert!(find(&mut components, i) == find(&mut components, original));
+        }
+
+        let dendro = create_dendrogram(&matrix, finder, &mut rng);
+        let mut clusters = dendrogram::find_clusters(&dendro, cluster_size);
+        clusters.sort();
+
+        assert!(clusters.len() == 2);
+        assert!(clusters[0] == (0..cluster_size).collect::<Vec<usize>>());
+        assert!(clusters[1] == (cluster_size..2 * cluster_size).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_all_pairs_matches_sorted_projection() {
+        use rand::SeedableRng;
+
+        // seeded, as the correlation below depends on the data and on the column orders
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let cluster_size = 100;
+        let mut matrix = create_seeded_matrix(&mut rng, cluster_size, 3, 0..4);
+        matrix.extend(create_seeded_matrix(&mut rng, cluster_size, 3, 5..10));
+        let blobs = vec![
+            (0..cluster_size).collect::<Vec<usize>>(),
+            (cluster_size..2 * cluster_size).collect::<Vec<usize>>(),
+        ];
+
+        let exact = create_dendrogram(&matrix, AllPairs, &mut rng);
+        let approx = create_dendrogram(&matrix, None, &mut rng);
+
+        // both split the noisy blobs apart
+        for dendro in [&exact, &approx] {
+            let mut clusters = find_k_clusters(dendro, 2);
+            clusters.sort();
+            assert!(clusters == blobs);
+        }
+
+        // and the approximate dendrogram preserves the exact cophenetic distances
+        let exact_index = CopheneticIndex::new(&exact);
+        let correlation =
+            cophenetic_correlation(&approx, |i, j| exact_index.cophenetic_distance(i, j));
+        // the clustering is reproducible for a seeded rng, and this seed gives about 0.964
+        assert!(correlation > 0.9);
+    }
+
+    #[test]
+    fn test_sorted_projection_window() {
+        use rand::seq::SliceRandom;
+
+        let num_rows = 50;
+        let mut rng = rand::thread_rng();
+        let mut matrix: Vec<Vec<i32>> = (0..num_rows as i32).map(|v| vec![v]).collect();
+        matrix.shuffle(&mut rng);
+
+        // the rows in increasing order of their distinct values
+        let mut order: Vec<usize> = (0..num_rows).collect();
+        order.sort_by_key(|i| matrix[*i][0]);
+        let window_pairs = |window: usize| -> HashSet<(usize, usize)> {
+            (1..=window)
+                .flat_map(|d| (0..num_rows - d).map(move |i| (i, i + d)))
+                .map(|(i, j)| (order[i].min(order[j]), order[i].max(order[j])))
+                .collect()
+        };
+
+        let chain = SortedProjection::default()
+            .find_neighbors(&matrix, &mut rng)
+            .unwrap();
+        assert!(chain == window_pairs(1));
+
+        let finder = SortedProjection {
+            window: 3,
+            ..Default::default()
+        };
+        let neighbors = finder.find_neighbors(&matrix, &mut rng).unwrap();
+        assert!(neighbors.len() == 49 + 48 + 47);
+        assert!(neighbors == window_pairs(3));
+
+        let dendro = create_dendrogram(&matrix, finder, &mut rng);
+        assert!(dendro.size() == 50);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_sorted_projection_parallel_matches_serial() {
+        use rand::SeedableRng;
+
+        // many duplicated rows, whose order in the sorts depends on where the sorts start from
+        let matrix = create_random_matrix(2000, 4, 0..3);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let col_orders = neighbors::column_orders(4, 3, &mut rng);
+        let keys = sort_keys::SortKeys::extract(&matrix);
+        for keys in [None, Some(&keys)] {
+            let serial = neighbors::link_sorted_rows(&matrix, keys, &col_orders, 2);
+            let parallel = neighbors::par_link_sorted_rows(&matrix, keys, &col_orders, 2);
+            assert!(serial == parallel);
+        }
+
+        let finder = SortedProjection {
+            init_iterations: 3,
+            ..Default::default()
+        };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let neighbors = finder.find_neighbors(&matrix, &mut rng).unwrap();
+        assert!(neighbors == neighbors::link_sorted_rows(&matrix, None, &col_orders, 1));
+    }
+
+    #[test]
+    fn test_precomputed_keys_sort_like_values() {
+        let mut rng = rand::thread_rng();
+        let mut matrix = create_random_matrix(1000, 3, 0..4);
+        for row in matrix.iter_mut() {
+            row.push(rng.gen_range(-1000..1000));
+        }
+        let col_order = [2, 0, 3, 1];
+
+        let keys = sort_keys::SortKeys::extract(&matrix);
+        let mut row_indices: Vec<usize> = (0..matrix.len()).collect();
+        keys.sort_rows(&col_order, &mut row_indices, &mut Vec::new());
+
+        let sorted: Vec<Vec<i32>> = row_indices
+            .iter()
+            .map(|r| col_order.iter().map(|c| matrix[*r][*c]).collect())
+            .collect();
+        let mut expected = sorted.clone();
+        expected.sort();
+        assert!(sorted == expected);
+
+        row_indices.sort();
+        assert!(row_indices == (0..matrix.len()).collect::<Vec<usize>>());
+
+        // the keys order -0.0, 0.0 and NaN like the comparisons of values do
+        let specials = [-1.0, -0.0, 0.0, 1.0, f32::NAN, -f32::NAN];
+        let col_order = [2, 0, 1];
+        let data = FloatData {
+            rows: (0..200)
+                .map(|_| {
+                    (0..3)
+                        .map(|_| specials[rng.gen_range(0..specials.len())])
+                        .collect()
+                })
+                .collect(),
+        };
+        let compare_rows = |i: &usize, j: &usize| {
+            col_order
+                .iter()
+                .map(|c| sort_keys::compare_values(data.rows[*i][*c], data.rows[*j][*c]))
+                .find(|ordering| *ordering != std::cmp::Ordering::Equal)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        };
+        let keys = sort_keys::SortKeys::extract(&data);
+        let mut row_indices: Vec<usize> = (0..data.rows.len()).collect();
+        keys.sort_rows(&col_order, &mut row_indices, &mut Vec::new());
+        let mut expected = row_indices.clone();
+        expected.sort_by(compare_rows);
+        for (i, j) in row_indices.iter().zip(&expected) {
+            assert!(compare_rows(i, j) == std::cmp::Ordering::Equal);
+        }
+    }
+
+    struct FloatData {
+        rows: Vec<Vec<f32>>,
+    }
+
+    impl TypedIndexableData for FloatData {
+        type Summary = MaskSummary;
+
+        fn value(&self, row_index: usize, column_index: usize) -> f32 {
+            self.rows[row_index][column_index]
+        }
+
+        fn num_columns(&self) -> usize {
+            3
+        }
+
+        fn num_rows(&self) -> usize {
+            self.rows.len()
+        }
+
+        fn create_summary(&self, _row_index: usize) -> MaskSummary {
+            MaskSummary { mask: 0 }
+        }
+    }
+
+    /// A summary whose distance decreases when clusters grow, which breaks complete-linkage.
+    struct ShrinkingDistance {
+        rows: usize,
+    }
+
+    impl data::ClusterSummary for ShrinkingDistance {
+        fn summary_size(&self) -> usize {
+            self.rows
+        }
+        fn distance(&self, other: &dyn data::ClusterSummary) -> f32 {
+            1.0 / (self.rows + other.summary_size()) as f32
+        }
+        fn extend(&mut self, other: &dyn data::ClusterSummary) {
+            self.rows += other.summary_size();
+        }
+        fn clear(&mut self) {}
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    struct ShrinkingData {
+        num_rows: usize,
+    }
+
+    impl data::IndexableData for ShrinkingData {
+        fn get_value(&self, row_index: usize, _column_index: usize) -> f32 {
+            row_index as f32
+        }
+        fn get_num_columns(&self) -> usize {
+            1
+        }
+        fn get_num_rows(&self) -> usize {
+            self.num_rows
+        }
+        fn create_cluster_summary(&self, row_index: usize) -> Box<dyn data::ClusterSummary> {
+            // row 0 is heavier so that the first clusters are merged into it, whose links are then recomputed
+            let rows = if row_index == 0 { 2 } else { 1 };
+            Box::new(ShrinkingDistance { rows })
+        }
+    }
+
+    /// A summary counting how many distances are computed.
+    struct CountingSummary {
+        num_distances: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl data::ClusterSummary for CountingSummary {
+        fn summary_size(&self) -> usize {
+            1
+        }
+        fn distance(&self, _other: &dyn data::ClusterSummary) -> f32 {
+            self.num_distances
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            1.0
+        }
+        fn extend(&mut self, _other: &dyn data::ClusterSummary) {}
+        fn clear(&mut self) {}
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    struct CountingData {
+        num_distances: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl data::IndexableData for CountingData {
+        fn get_value(&self, row_index: usize, _column_index: usize) -> f32 {
+            row_index as f32
+        }
+        fn get_num_columns(&self) -> usize {
+            3
+        }
+        fn get_num_rows(&self) -> usize {
+            2
+        }
+        fn create_cluster_summary(&self, _row_index: usize) -> Box<dyn data::ClusterSummary> {
+            Box::new(CountingSummary {
+                num_distances: self.num_distances.clone(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_initial_links_are_not_recomputed() {
+        let mut rng = rand::thread_rng();
+        let data = CountingData {
+            num_distances: Default::default(),
+        };
+        let dendro = create_dendrogram(&data, AllPairs, &mut rng);
+        assert!(dendro.size() == 2);
+
+        // the summaries are 1 category large whatever the number of columns, and they don't change
+        // before the rows are merged, so the distance is only computed once
+        let num_distances = data
+            .num_distances
+            .load(std::sync::atomic::Ordering::Relaxed);
+        assert!(num_distances == 1);
+    }
+
+    #[test]
+    fn test_try_create_dendrogram_errors() {
+        let mut rng = rand::thread_rng();
+
+        let empty: Vec<Vec<i32>> = Vec::new();
+        let result = try_create_dendrogram(&empty, None, &mut rng);
+        assert!(result.err() == Some(ClusteringError::EmptyData));
+
+        let no_columns: Vec<Vec<i32>> = vec![Vec::new(); 10];
+        let result = try_create_dendrogram(&no_columns, None, &mut rng);
+        assert!(result.err() == Some(ClusteringError::NoColumns));
+
+        let matrix = create_random_matrix(10, 3, 0..4);
+        let result = try_create_dendrogram(&matrix, Some(0), &mut rng);
+        assert!(matches!(result, Err(ClusteringError::InvalidParameter(_))));
+
+        for pair in [(5, 1000), (3, 3), (4, 2)] {
+            let finder = PairsFinder {
+                pairs: vec![(0, 1), pair],
+            };
+            let result = try_create_dendrogram(&matrix, finder, &mut rng);
+            assert!(matches!(result, Err(ClusteringError::InvalidParameter(_))));
+        }
+
+        let single_row = create_random_matrix(1, 3, 0..4);
+        let dendro = try_create_dendrogram(&single_row, None, &mut rng).unwrap();
+        assert!(matches!(dendro, dendrogram::Dendrogram::Leaf(0)));
+
+        let shrinking = ShrinkingData { num_rows: 10 };
+        let result = try_create_dendrogram(&shrinking, AllPairs, &mut rng);
+        match result {
+            Err(ClusteringError::MonotonicityViolation(v)) => {
+                assert!(v.new_distance < v.previous_distance)
+            }
+            _ => panic!("the violation was not reported"),
+        }
+    }
+
+    #[test]
+    fn test_diagnostics_report_violations() {
+        let mut rng = rand::thread_rng();
+
+        let shrinking = ShrinkingData { num_rows: 10 };
+        let (dendro, diagnostics) =
+            create_dendrogram_with_diagnostics(&shrinking, AllPairs, &mut rng).unwrap();
+        assert!(dendro.size() == 10);
+        assert!(!diagnostics.is_valid());
+        assert!(diagnostics.violations.len() <= diagnostics.num_violations);
+        for v in &diagnostics.violations {
+            assert!(v.new_distance < v.previous_distance);
+        }
+
+        let matrix = create_random_matrix(100, 3, 0..4);
+        let (dendro, diagnostics) =
+            create_dendrogram_with_diagnostics(&matrix, None, &mut rng).unwrap();
+        assert!(dendro.size() == 100);
+        assert!(diagnostics.is_valid());
+    }
+
+    #[test]
+    fn test_check_summary() {
+        let mut rng = rand::thread_rng();
+
+        let matrix = create_random_matrix(100, 3, 0..4);
+        let report = check_summary(&matrix, 100, &mut rng);
+        assert!(report.num_checks > 0);
+        assert!(report.is_ok());
+
+        let shrinking = ShrinkingData { num_rows: 100 };
+        let report = check_summary(&shrinking, 100, &mut rng);
+        assert!(!report.is_ok());
+        assert!(report
+            .violations
+            .iter()
+            .all(|v| matches!(v, SummaryViolation::Monotonicity { .. })));
+
+        let empty: Vec<Vec<i32>> = Vec::new();
+        assert!(check_summary(&empty, 100, &mut rng).num_checks == 0);
+    }
+
+    /// A dendrogram where each node merges the previous one with the next row.
+    fn create_chain_dendrogram(n_rows: usize) -> Dendrogram {
+        let mut dendro = Dendrogram::Leaf(0);
+        for row_index in 1..n_rows {
+            dendro = Dendrogram::Node(
+                Box::new(dendro),
+                Box::new(Dendrogram::Leaf(row_index)),
+                row_index as f32,
+                row_index + 1,
+            );
+        }
+        dendro
+    }
+
+    #[test]
+    fn test_traversal() {
+        let dendro = create_chain_dendrogram(4);
+        let (left, right) = dendro.children().unwrap();
+        assert!(left.size() == 3 && left.distance() == 2.0);
+        assert!(right.row_index() == Some(3) && right.is_leaf());
+
+        let pre_order: Vec<usize> = dendro.pre_order().map(|n| n.size()).collect();
+        assert!(pre_order == vec![4, 3, 2, 1, 1, 1, 1]);
+        let post_order: Vec<f32> = dendro.post_order().map(|n| n.distance()).collect();
+        assert!(post_order == vec![0.0, 0.0, 1.0, 0.0, 2.0, 0.0, 3.0]);
+        assert!(dendro.leaves().collect::<Vec<usize>>() == vec![0, 1, 2, 3]);
+
+        // deep dendrograms must not overflow the stack
+        let n_rows = 500_000;
+        let deep = create_chain_dendrogram(n_rows);
+        assert!(deep.pre_order().count() == 2 * n_rows - 1);
+        assert!(deep.post_order().count() == 2 * n_rows - 1);
+        assert!(deep.leaves().count() == n_rows);
+    }
+
+    #[test]
+    fn test_cut_by_distance() {
+        let dendro = create_chain_dendrogram(5);
+        let mut clusters = find_clusters_by_distance(&dendro, 2.0);
+        clusters.sort();
+        assert!(clusters == vec![vec![0, 1, 2], vec![3], vec![4]]);
+
+        let mut assignments = vec![0; 2];
+        let num_clusters = assign_rows_to_clusters_by_distance(&dendro, &mut assignments, 0.5);
+        assert!(num_clusters == 5 && assignments.len() == 5);
+
+        // 3 columns with either 0 or 10 values are at distance 6 from each other
+        let cluster_size = 100;
+        let mut matrix = create_random_matrix(cluster_size, 3, 0..1);
+        matrix.extend(create_random_matrix(cluster_size, 3, 10..11));
+        let mut rng = rand::thread_rng();
+        let dendro = create_dendrogram(&matrix, None, &mut rng);
+        let mut clusters = find_clusters_by_distance(&dendro, 3.0);
+        clusters.sort();
+        assert!(clusters.len() == 2);
+        assert!(clusters[0] == (0..cluster_size).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_k_clusters() {
+        let dendro = create_chain_dendrogram(5);
+        let mut clusters = find_k_clusters(&dendro, 3);
+        clusters.sort();
+        assert!(clusters == vec![vec![0, 1, 2], vec![3], vec![4]]);
+        assert!(find_k_clusters(&dendro, 0).len() == 1);
+        assert!(find_k_clusters(&dendro, 10).len() == 5);
+
+        // user-defined distances can be negative, which must not stop the splits at a leaf
+        let negative = join(
+            join(Dendrogram::Leaf(0), Dendrogram::Leaf(1), -0.5),
+            Dendrogram::Leaf(2),
+            -1.0,
+        );
+        assert!(find_k_clusters(&negative, 3).len() == 3);
+        assert!(find_k_clusters(&negative, 2) == vec![vec![0, 1], vec![2]]);
+
+        // ties: all the nodes have the same distance
+        let mut rng = rand::thread_rng();
+        let matrix = create_random_matrix(200, 3, 0..1);
+        let dendro = create_dendrogram(&matrix, None, &mut rng);
+        let mut assignments = Vec::new();
+        for k in 1..50 {
+            assert!(assign_rows_to_k_clusters(&dendro, &mut assignments, k) == k);
+            let first = find_k_clusters(&dendro, k);
+            assert!(first == find_k_clusters(&dendro, k));
+            assert!(first.iter().all(|c| !c.is_empty()));
+        }
+    }
+
+    #[test]
+    fn test_cut_with_min_size() {
+        // ((0..4) + 4) + 5, at distances 3, 4 and 5
+        let dendro = create_chain_dendrogram(6);
+
+        let (clusters, outliers) =
+            find_clusters_with_min_size(&dendro, 4, 2, UndersizedClusters::Outliers);
+        assert!(clusters == vec![vec![0, 1, 2, 3]]);
+        assert!(outliers == vec![4, 5]);
+
+        let (mut clusters, outliers) =
+            find_clusters_with_min_size(&dendro, 4, 2, UndersizedClusters::MergeIntoSibling);
+        clusters.sort();
+        assert!(clusters == vec![vec![0, 1, 2, 3, 4, 5]]);
+        assert!(outliers.is_empty());
+
+        let mut assignments = Vec::new();
+        let num_clusters = assign_rows_to_clusters_with_min_size(
+            &dendro,
+            &mut assignments,
+            2,
+            1,
+            UndersizedClusters::Outliers,
+        );
+        assert!(num_clusters == 5);
+        assert!(assignments.iter().all(|a| *a != OUTLIER));
+    }
+
+    fn join(cluster1: Dendrogram, cluster2: Dendrogram, distance: f32) -> Dendrogram {
+        let size = cluster1.size() + cluster2.size();
+        Dendrogram::Node(Box::new(cluster1), Box::new(cluster2), distance, size)
+    }
+
+    /// A dendrogram where all the rows of the range are merged at the same distance.
+    fn create_flat_dendrogram(rows: std::ops::Range<usize>, distance: f32) -> Dendrogram {
+        let mut dendro = Dendrogram::Leaf(rows.start);
+        for row_index in rows.start + 1..rows.end {
+            dendro = join(dendro, Dendrogram::Leaf(row_index), distance);
+        }
+        dendro
+    }
+
+    #[test]
+    fn test_dynamic_cut() {
+        // a dense branch made of two tight clusters, and a loose branch
+        let dense = join(
+            create_flat_dendrogram(0..5, 1.0),
+            create_flat_dendrogram(5..10, 2.0),
+            10.0,
+        );
+        let loose = join(
+            create_flat_dendrogram(10..15, 5.0),
+            create_flat_dendrogram(15..20, 50.0),
+            60.0,
+        );
+        let dendro = join(dense, loose, 100.0);
+
+        let mut clusters = find_clusters_dynamically(&dendro, 3, 0.3);
+        clusters.sort();
+        assert!(clusters.len() == 3);
+        assert!(clusters[0] == (0..5).collect::<Vec<usize>>());
+        assert!(clusters[1] == (5..10).collect::<Vec<usize>>());
+        assert!(clusters[2] == (10..20).collect::<Vec<usize>>());
+
+        // out-of-range gaps are clamped
+        assert!(find_clusters_dynamically(&dendro, 3, 1.0).len() == 1);
+        assert!(find_clusters_dynamically(&dendro, 3, 5.0).len() == 1);
+        assert!(find_clusters_dynamically(&dendro, 3, f32::NAN).len() == 1);
+        assert!(
+            find_clusters_dynamically(&dendro, 3, -1.0)
+                == find_clusters_dynamically(&dendro, 3, 0.0)
+        );
+
+        // tight clusters under a loose branch that doesn't stand out from the root
+        let loose = join(
+            create_flat_dendrogram(0..5, 1.0),
+            create_flat_dendrogram(5..10, 1.0),
+            95.0,
+        );
+        let dendro = join(loose, create_flat_dendrogram(10..20, 10.0), 100.0);
+        let mut clusters = find_clusters_dynamically(&dendro, 3, 0.3);
+        clusters.sort();
+        assert!(clusters.len() == 3);
+        assert!(clusters[0] == (0..5).collect::<Vec<usize>>());
+        assert!(clusters[1] == (5..10).collect::<Vec<usize>>());
+        assert!(clusters[2] == (10..20).collect::<Vec<usize>>());
+
+        // small branches are kept with their sibling
+        let mut assignments = Vec::new();
+        let chain = create_chain_dendrogram(20);
+        assert!(assign_rows_to_clusters_dynamically(&chain, &mut assignments, 2, 0.3) == 1);
+        assert!(assignments.iter().all(|a| *a == 0));
+    }
+
+    #[test]
+    fn test_stable_clusters() {
+        // two tight clusters, and two noisy rows merged late
+        let tight = join(
+            create_flat_dendrogram(0..10, 1.0),
+            create_flat_dendrogram(10..20, 1.0),
+            10.0,
+        );
+        let dendro = join(
+            join(tight, Dendrogram::Leaf(20), 50.0),
+            Dendrogram::Leaf(21),
+            60.0,
+        );
+
+        let tree = CondensedTree::new(&dendro, 5);
+        assert!(tree.clusters().len() == 3);
+        assert!(tree.clusters()[1].size == 10);
+        assert!((tree.clusters()[1].stability - 9.0).abs() < 1e-9);
+
+        let (mut clusters, noise) = find_stable_clusters(&dendro, 5);
+        clusters.sort();
+        assert!(clusters.len() == 2);
+        assert!(clusters[0] == (0..10).collect::<Vec<usize>>());
+        assert!(clusters[1] == (10..20).collect::<Vec<usize>>());
+        assert!(noise == vec![20, 21]);
+
+        // a stable parent wins over its less stable children
+        let dendro = join(
+            join(
+                create_flat_dendrogram(0..10, 9.0),
+                create_flat_dendrogram(10..20, 9.0),
+                10.0,
+            ),
+            create_flat_dendrogram(20..30, 1.0),
+            100.0,
+        );
+        let mut assignments = Vec::new();
+        assert!(assign_rows_to_stable_clusters(&dendro, &mut assignments, 5) == 2);
+        assert!(assignments[0] == assignments[15]);
+        assert!(assignments[0] != assignments[25]);
+    }
+
+    #[test]
+    fn test_linkage_matrix() {
+        let dendro = join(
+            join(create_flat_dendrogram(0..3, 1.0), Dendrogram::Leaf(3), 2.0),
+            Dendrogram::Leaf(4),
+            5.0,
+        );
+        let matrix = dendro.to_linkage_matrix();
+        assert!(
+            matrix
+                == vec![
+                    [0.0, 1.0, 1.0, 2.0],
+                    [5.0, 2.0, 1.0, 3.0],
+                    [6.0, 3.0, 2.0, 4.0],
+                    [7.0, 4.0, 5.0, 5.0],
+                ]
+        );
+        let rebuilt = Dendrogram::from_linkage_matrix(&matrix).unwrap();
+        assert!(rebuilt.to_linkage_matrix() == matrix);
+        assert!(
+            rebuilt.leaves().collect::<Vec<usize>>() == dendro.leaves().collect::<Vec<usize>>()
+        );
+
+        // the merges are listed by increasing distance, children first
+        let dendro = join(
+            join(Dendrogram::Leaf(0), Dendrogram::Leaf(1), 3.0),
+            join(Dendrogram::Leaf(2), Dendrogram::Leaf(3), 1.0),
+            4.0,
+        );
+        let matrix = dendro.to_linkage_matrix();
+        assert!(
+            matrix
+                == vec![
+                    [2.0, 3.0, 1.0, 2.0],
+                    [0.0, 1.0, 3.0, 2.0],
+                    [5.0, 4.0, 4.0, 4.0],
+                ]
+        );
+        let inverted = join(
+            join(Dendrogram::Leaf(0), Dendrogram::Leaf(1), 3.0),
+            Dendrogram::Leaf(2),
+            2.0,
+        );
+        assert!(inverted.to_linkage_matrix() == vec![[0.0, 1.0, 3.0, 2.0], [3.0, 2.0, 2.0, 3.0]]);
+
+        let mut rng = rand::thread_rng();
+        let data = create_random_matrix(200, 4, 0..10);
+        let matrix = create_dendrogram(&data, None, &mut rng).to_linkage_matrix();
+        assert!(matrix.windows(2).all(|rows| rows[0][2] <= rows[1][2]));
+
+        // the rows of a compact dendrogram follow its nodes
+        let compact = create_compact_dendrogram(&data, None, &mut rng);
+        for (i, row) in compact.to_linkage_matrix().iter().enumerate() {
+            let node = compact.num_rows() + i;
+            let (id1, id2) = compact.children(node).unwrap();
+            let distance = compact.distance(node) as f64;
+            let size = compact.size(node) as f64;
+            assert!(*row == [id1 as f64, id2 as f64, distance, size]);
+        }
+
+        // deep trees don't overflow the stack
+        let chain = create_chain_dendrogram(500_000);
+        let matrix = chain.to_linkage_matrix();
+        assert!(matrix.len() == 499_999);
+        let rebuilt = Dendrogram::from_linkage_matrix(&matrix).unwrap();
+        assert!(rebuilt.size() == 500_000);
+
+        assert!(Dendrogram::from_linkage_matrix(&[]).unwrap().is_leaf());
+        for invalid in [
+            [[0.0, 0.0, 1.0, 2.0]],
+            [[0.0, 2.0, 1.0, 2.0]],
+            [[0.0, 1.5, 1.0, 2.0]],
+            [[0.0, 1.0, 1.0, 3.0]],
+        ] {
+            assert!(matches!(
+                Dendrogram::from_linkage_matrix(&invalid),
+                Err(ClusteringError::InvalidLinkageMatrix(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let dendro = join(
+            join(Dendrogram::Leaf(0), Dendrogram::Leaf(1), 1.0),
+            Dendrogram::Leaf(2),
+            3.0,
+        );
+        let mut newick = Vec::new();
+        dendro.write_newick(&mut newick, None).unwrap();
+        assert!(String::from_utf8(newick).unwrap() == "((0:1,1:1):2,2:3);\n");
+
+        let names = ["a", "b c", "d'e"];
+        let labels = |row_index: usize| names[row_index].to_string();
+        let mut newick = Vec::new();
+        dendro.write_newick(&mut newick, Some(&labels)).unwrap();
+        assert!(String::from_utf8(newick).unwrap() == "((a:1,'b c':1):2,'d''e':3);\n");
+
+        let mut dot = Vec::new();
+        dendro.write_dot(&mut dot, Some(&labels)).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph dendrogram {\n"));
+        assert!(dot.contains("  0 [label=\"d=3\\nn=3\"];\n"));
+        assert!(dot.contains("  2 [label=\"a\", shape=box];\n"));
+        assert!(dot.contains("  1 -> 3;\n"));
+        assert!(dot.lines().filter(|line| line.contains("->")).count() == 4);
+
+        // deep trees don't overflow the stack
+        let chain = create_chain_dendrogram(500_000);
+        chain.write_newick(std::io::sink(), None).unwrap();
+        chain.write_dot(std::io::sink(), None).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let dendro = join(
+            join(Dendrogram::Leaf(3), Dendrogram::Leaf(1), 1.5),
+            Dendrogram::Leaf(2),
+            3.0,
+        );
+        let flatten = |d: &Dendrogram| {
+            d.post_order()
+                .map(|node| (node.row_index(), node.distance(), node.size()))
+                .collect::<Vec<_>>()
+        };
+        let json = serde_json::to_string(&dendro).unwrap();
+        assert!(json == r#"[{"Leaf":3},{"Leaf":1},{"Node":1.5},{"Leaf":2},{"Node":3.0}]"#);
+        let rebuilt: Dendrogram = serde_json::from_str(&json).unwrap();
+        assert!(flatten(&rebuilt) == flatten(&dendro));
+
+        let bytes = bincode::serialize(&dendro).unwrap();
+        let rebuilt: Dendrogram = bincode::deserialize(&bytes).unwrap();
+        assert!(flatten(&rebuilt) == flatten(&dendro));
+
+        assert!(serde_json::from_str::<Dendrogram>(r#"[{"Leaf":0},{"Node":1.0}]"#).is_err());
+        assert!(serde_json::from_str::<Dendrogram>(r#"[{"Leaf":0},{"Leaf":1}]"#).is_err());
+        assert!(serde_json::from_str::<Dendrogram>("[]").is_err());
+
+        // deep trees don't overflow the stack
+        let chain = create_chain_dendrogram(500_000);
+        let bytes = bincode::serialize(&chain).unwrap();
+        let rebuilt: Dendrogram = bincode::deserialize(&bytes).unwrap();
+        assert!(rebuilt.size() == 500_000);
+        let json = serde_json::to_string(&chain).unwrap();
+        let rebuilt: Dendrogram = serde_json::from_str(&json).unwrap();
+        assert!(rebuilt.size() == 500_000);
+    }
+
+    #[test]
+    fn test_compact_dendrogram() {
+        let cluster_size = 100;
+        let mut matrix = create_random_matrix(cluster_size, 3, 0..4);
+        matrix.extend(create_random_matrix(cluster_size, 3, 5..10));
+
+        let mut rng = rand::thread_rng();
+        let compact = create_compact_dendrogram(&matrix, None, &mut rng);
+        assert!(compact.num_rows() == 2 * cluster_size);
+        assert!(compact.num_nodes() == 4 * cluster_size - 1);
+        assert!(compact.parent(compact.root()).is_none());
+        let clusters = find_clusters(&compact, cluster_size);
+        assert!(clusters.len() == 2);
+        assert!(clusters[0].len() == cluster_size);
+
+        // the cuts give the same results on both representations
+        let boxed = compact.to_dendrogram();
+        let converted = CompactDendrogram::from_dendrogram(&boxed).unwrap();
+        assert!(converted.to_dendrogram().to_linkage_matrix() == boxed.to_linkage_matrix());
+        assert!(compact.leaves().eq(boxed.leaves()));
+        assert!(find_clusters(&compact, 10) == find_clusters(&boxed, 10));
+        assert!(find_k_clusters(&compact, 7) == find_k_clusters(&boxed, 7));
+        assert!(find_clusters_by_distance(&compact, 2.0) == find_clusters_by_distance(&boxed, 2.0));
+        assert!(
+            find_clusters_dynamically(&compact, 5, 0.2)
+                == find_clusters_dynamically(&boxed, 5, 0.2)
+        );
+        assert!(find_stable_clusters(&compact, 5) == find_stable_clusters(&boxed, 5));
+
+        // deep trees don't overflow the stack
+        let chain = create_chain_dendrogram(500_000);
+        let compact = CompactDendrogram::from_dendrogram(&chain).unwrap();
+        assert!(compact.post_order().count() == 999_999);
+        assert!(compact.to_dendrogram().size() == 500_000);
+
+        let invalid = join(Dendrogram::Leaf(0), Dendrogram::Leaf(2), 1.0);
+        assert!(CompactDendrogram::from_dendrogram(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_cophenetic() {
+        let dendro = join(
+            join(Dendrogram::Leaf(0), Dendrogram::Leaf(1), 1.0),
+            join(
+                Dendrogram::Leaf(2),
+                join(Dendrogram::Leaf(3), Dendrogram::Leaf(4), 0.5),
+                2.0,
+            ),
+            5.0,
+        );
+        let index = CopheneticIndex::new(&dendro);
+        assert!(index.lca(0, 1).size() == 2);
+        assert!(index.lca(2, 4).size() == 3);
+        assert!(index.lca(4, 0).size() == 5);
+        assert!(index.lca(3, 3).row_index() == Some(3));
+        assert!(index.cophenetic_distance(0, 1) == 1.0);
+        assert!(index.cophenetic_distance(3, 4) == 0.5);
+        assert!(index.cophenetic_distance(4, 2) == 2.0);
+        assert!(index.cophenetic_distance(1, 3) == 5.0);
+        assert!(index.cophenetic_distance(2, 2) == 0.0);
+
+        let correlation = cophenetic_correlation(&dendro, |i, j| index.cophenetic_distance(i, j));
+        assert!((correlation - 1.0).abs() < 1e-9);
+        let correlation = cophenetic_correlation(&dendro, |i, j| (i as f32 - j as f32).abs());
+        assert!(correlation > 0.0 && correlation < 1.0);
+
+        let compact = CompactDendrogram::from_dendrogram(&dendro).unwrap();
+        let compact_index = CopheneticIndex::new(&compact);
+        assert!(compact.size(compact_index.lca(2, 4)) == 3);
+        assert!(compact_index.cophenetic_distance(1, 3) == 5.0);
+
+        // deep trees don't overflow the stack
+        let chain = create_chain_dendrogram(500_000);
+        let index = CopheneticIndex::new(&chain);
+        assert!(index.cophenetic_distance(0, 1) == 1.0);
+        assert!(index.cophenetic_distance(123_456, 400_000) == 400_000.0);
+        assert!(index.lca(499_999, 0).size() == 500_000);
+    }
+
+    #[test]
+    fn test_optimal_leaf_order() {
+        let mut rng = rand::thread_rng();
+        let matrix = create_random_matrix(9, 4, 0..3);
+        let distance = |i: usize, j: usize| {
+            let row1: &Vec<i32> = &matrix[i];
+            row1.iter().zip(&matrix[j]).filter(|(a, b)| a != b).count() as f32
+        };
+        let path_cost =
+            |order: &[usize]| -> f32 { order.windows(2).map(|w| distance(w[0], w[1])).sum() };
+
+        let compact = create_compact_dendrogram(&matrix, None, &mut rng);
+        let order = optimal_leaf_order(&compact, distance);
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert!(sorted == (0..9).collect::<Vec<usize>>());
+
+        // brute force over all the ways of swapping children
+        let mut best = f32::INFINITY;
+        for mask in 0..(1 << 8) {
+            let mut flipped = compact.clone();
+            for bit in 0..8 {
+                if mask & (1 << bit) != 0 {
+                    flipped.swap_children(9 + bit);
+                }
+            }
+            best = best.min(path_cost(&flipped.leaves().collect::<Vec<usize>>()));
+        }
+        assert!(path_cost(&order) == best);
+
+        let mut reordered = compact.clone();
+        reordered.optimize_leaf_order(distance);
+        assert!(reordered.leaves().collect::<Vec<usize>>() == order);
+        let mut boxed = compact.to_dendrogram();
+        boxed.optimize_leaf_order(distance);
+        assert!(boxed.leaves().collect::<Vec<usize>>() == order);
+    }
+
+    #[test]
+    fn test_render() {
+        let dendro = join(
+            join(Dendrogram::Leaf(0), Dendrogram::Leaf(1), 1.0),
+            join(Dendrogram::Leaf(2), Dendrogram::Leaf(3), 0.5),
+            5.0,
+        );
+        let options = RenderOptions {
+            max_cluster_size: 2,
+            ..Default::default()
+        };
+        let mut text = Vec::new();
+        let labels = |row_index: usize| format!("row{}", row_index);
+        dendro
+            .write_text(&mut text, &options, Some(&labels))
+            .unwrap();
+        assert!(
+            String::from_utf8(text).unwrap() == "d=5 n=4\n├── [2 rows, d=1]\n└── [2 rows, d=0.5]\n"
+        );
+
+        let options = RenderOptions {
+            unicode: false,
+            ..Default::default()
+        };
+        let mut text = Vec::new();
+        dendro
+            .write_text(&mut text, &options, Some(&labels))
+            .unwrap();
+        let expected = "d=5 n=4\n|-- d=1 n=2\n|   |-- row0\n|   `-- row1\n`-- d=0.5 n=2\n    |-- row2\n    `-- row3\n";
+        assert!(String::from_utf8(text).unwrap() == expected);
+
+        let mut svg = Vec::new();
+        dendro
+            .write_svg(&mut svg, &RenderOptions::default(), None)
+            .unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.matches("<path ").count() == 3);
+        assert!(svg.matches("<text ").count() == 4);
+
+        // nodes higher than the root stay in the image
+        let inverted = join(
+            join(Dendrogram::Leaf(0), Dendrogram::Leaf(1), 4.0),
+            Dendrogram::Leaf(2),
+            1.0,
+        );
+        let mut svg = Vec::new();
+        inverted
+            .write_svg(&mut svg, &RenderOptions::default(), None)
+            .unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        for path in svg.lines().filter(|line| line.starts_with("<path ")) {
+            let coordinates = path["<path d=\"M".len()..path.len() - "\"/>".len()]
+                .split(|c: char| c == ' ' || c.is_ascii_uppercase())
+                .filter(|coordinate| !coordinate.is_empty());
+            for coordinate in coordinates {
+                assert!(coordinate.parse::<f32>().unwrap() >= 0.0);
+            }
+        }
+
+        // deep trees are truncated
+        let chain = create_chain_dendrogram(500_000);
+        let options = RenderOptions {
+            max_depth: 3,
+            ..Default::default()
+        };
+        let mut text = Vec::new();
+        chain.write_text(&mut text, &options, None).unwrap();
+        assert!(String::from_utf8(text).unwrap().lines().count() == 7);
+        let mut svg = Vec::new();
+        chain.write_svg(&mut svg, &options, None).unwrap();
+        assert!(String::from_utf8(svg).unwrap().matches("<polygon ").count() == 1);
+    }
+
+    struct MaskSummary {
+        mask: u64,
+    }
+
+    impl TypedClusterSummary for MaskSummary {
+        fn summary_size(&self) -> usize {
+            self.mask.count_ones() as usize
+        }
+
+        fn distance(&self, other: &Self) -> f32 {
+            (self.mask | other.mask).count_ones() as f32
+        }
+
+        fn extend(&mut self, other: &Self) {
+            self.mask |= other.mask;
+        }
+
+        fn clear(&mut self) {
+            self.mask = 0;
+        }
+    }
+
+    /// Rows of 3 columns with values in 0..10, summarized with statically dispatched bitmasks.
+    struct MaskData {
+        rows: Vec<Vec<i32>>,
+    }
+
+    impl TypedIndexableData for MaskData {
+        type Summary = MaskSummary;
+
+        fn value(&self, row_index: usize, column_index: usize) -> f32 {
+            self.rows[row_index][column_index] as f32
+        }
+
+        fn num_columns(&self) -> usize {
+            3
+        }
+
+        fn num_rows(&self) -> usize {
+            self.rows.len()
+        }
+
+        fn create_summary(&self, row_index: usize) -> MaskSummary {
+            let mut mask = 0;
+            for (c, value) in self.rows[row_index].iter().enumerate() {
+                mask |= 1 << (10 * c + *value as usize);
+            }
+            MaskSummary { mask }
+        }
+    }
+
+    #[test]
+    fn test_typed_summary() {
+        use rand::SeedableRng;
+
+        // two blobs are occasionally split unevenly, so the data and the clustering are seeded
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let cluster_size = 100;
+        let mut rows = create_seeded_matrix(&mut rng, cluster_size, 3, 0..4);
+        rows.extend(create_seeded_matrix(&mut rng, cluster_size, 3, 5..10));
+        let data = MaskData { rows };
+
+        let dendro = try_create_dendrogram(&data, None, &mut rng).unwrap();
+        let clusters = find_clusters(&dendro, cluster_size);
+        assert!(clusters.len() == 2);
+        assert!(clusters[0].len() == cluster_size);
+
+        let dendro = create_compact_dendrogram(&data, MinHash::default(), &mut rng);
+        assert!(find_k_clusters(&dendro, 2)
+            .iter()
+            .all(|c| c.len() == cluster_size));
+
+        assert!(check_summary(&data, 100, &mut rng).is_ok());
+
+        // both traits are in scope here, and the methods of an IndexableData are not ambiguous
+        let matrix = create_random_matrix(10, 3, 0..4);
+        assert_eq!(matrix.get_num_rows(), matrix.num_rows());
+        assert_eq!(matrix.get_num_columns(), matrix.num_columns());
+        assert_eq!(matrix.get_value(9, 2), matrix.value(9, 2));
+        let (mut tokens1, mut tokens2) = (Vec::new(), Vec::new());
+        matrix.get_tokens(9, &mut tokens1);
+        matrix.tokens(9, &mut tokens2);
+        assert_eq!(tokens1, tokens2);
+    }
+
+    #[test]
+    fn test_bitset_summary() {
+        let schema = BitsetSchema::new(&[3, 70, 2]);
+        assert!(schema.num_bits() == 75);
+        let mut a = schema.summarize(&[0, 65, 1]).unwrap();
+        let b = schema.summarize(&[0, 2, 0]).unwrap();
+        assert!(schema.summarize(&[3, 0, 0]).is_err());
+        assert!(schema.summarize(&[0, 0]).is_err());
+        assert!(schema.summarize(&[0, 0, 0, 0]).is_err());
+        assert!(a.words().len() == 2);
+        assert!(a.contains(schema.bit(1, 65)) && !a.contains(schema.bit(1, 2)));
+        assert!(a.distance(&b) == 5.0);
+        assert!(ClusterSummary::distance(&a, &b) == 5.0);
+        a.extend(&b);
+        assert!(a.summary_size() == 5);
+        assert!(a.distance(&a) == 5.0);
+
+        assert!(BitsetData::new(&[vec![0, 1], vec![2]]).is_err());
+        assert!(BitsetData::with_schema(schema, &[vec![0, 70, 1]]).is_err());
+
+        let cluster_size = 100;
+        let mut rows = create_random_matrix(cluster_size, 3, 0..4);
+        rows.extend(create_random_matrix(cluster_size, 3, 5..10));
+        let rows: Vec<Vec<usize>> = rows
+            .iter()
+            .map(|row| row.iter().map(|v| *v as usize).collect())
+            .collect();
+        let data = BitsetData::new(&rows).unwrap();
+        assert!(data.schema().cardinality(0) <= 10);
+
+        let mut rng = rand::thread_rng();
+        let dendro = create_dendrogram(&data, None, &mut rng);
+        let clusters = find_clusters(&dendro, cluster_size);
+        assert!(clusters.len() == 2);
+        assert!(clusters[0].len() == cluster_size);
+
+        assert!(check_summary(&data, 100, &mut rng).is_ok());
+
+        let no_columns = BitsetData::new(&vec![Vec::new(); 10]).unwrap();
+        let result = try_create_dendrogram(&no_columns, None, &mut rng);
+        assert!(result.err() == Some(ClusteringError::NoColumns));
+    }
+
+    #[test]
+    fn test_hybrid_summary() {
+        use rand::SeedableRng;
+
+        let schema = HybridSchema::new(&[4, 1000, 3]);
+        assert!(schema.is_inline(0) && !schema.is_inline(1) && schema.is_inline(2));
+        assert!(schema.summarize(&[4, 0, 0]).is_err());
+        assert!(schema.summarize(&[0, 1000, 0]).is_err());
+        assert!(schema.summarize(&[0, 0]).is_err());
+        let huge = HybridSchema::new(&[1 << 40]);
+        assert!(huge.summarize(&[MAX_CATEGORY - 1]).is_ok());
+        assert!(huge.summarize(&[MAX_CATEGORY]).is_err());
+        assert!(huge.summarize(&[1 << 33]).is_err());
+
+        // two blobs are occasionally split unevenly below, so the data and the clustering are seeded
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+
+        // large enough to upgrade the high-cardinality column to a roaring bitmap
+        let mut summaries = Vec::new();
+        let mut sets = Vec::new();
+        for size in [2000, 5] {
+            let mut summary = schema.summarize(&[0, 0, 0]).unwrap();
+            let mut set = HashSet::from([(0, 0), (1, 0), (2, 0)]);
+            for _ in 0..size {
+                let row = [
+                    rng.gen_range(0..4),
+                    rng.gen_range(0..1000),
+                    rng.gen_range(0..3),
+                ];
+                summary.extend(&schema.summarize(&row).unwrap());
+                set.extend(row.iter().enumerate().map(|(c, v)| (c, *v)));
+            }
+            summaries.push(summary);
+            sets.push(set);
+        }
+        let expected = sets[0].len() + sets[1].len() - sets[0].intersection(&sets[1]).count();
+        assert!(summaries[0].summary_size() == sets[0].len());
+        assert!(summaries[0].distance(&summaries[1]) == expected as f32);
+        assert!(summaries[1].distance(&summaries[0]) == expected as f32);
+        let large = summaries[0].clone();
+        summaries[1].extend(&large);
+        assert!(summaries[1].summary_size() == sets[0].union(&sets[1]).count());
+
+        let cluster_size = 100;
+        let mut rows = create_seeded_matrix(&mut rng, cluster_size, 3, 0..4);
+        rows.extend(create_seeded_matrix(&mut rng, cluster_size, 3, 5..10));
+        let rows: Vec<Vec<usize>> = rows
+            .iter()
+            .map(|row| row.iter().map(|v| *v as usize).collect())
+            .collect();
+        let schema = HybridSchema::with_max_inline_cardinality(&[10, 100, 100], 10);
+        let data = HybridData::with_schema(schema, &rows).unwrap();
+
+        let dendro = create_dendrogram(&data, None, &mut rng);
+        let clusters = find_clusters(&dendro, cluster_size);
+        assert!(clusters.len() == 2);
+        assert!(clusters[0].len() == cluster_size);
+
+        assert!(check_summary(&data, 100, &mut rng).is_ok());
+
+        let no_columns = HybridData::new(&vec![Vec::new(); 10]).unwrap();
+        let result = try_create_dendrogram(&no_columns, None, &mut rng);
+        assert!(result.err() == Some(ClusteringError::NoColumns));
+    }
+
+    #[cfg(feature = "roaring")]
+    #[test]
+    fn test_hybrid_roaring_sets() {
+        use hybrid::CategorySet;
+        use std::collections::BTreeSet;
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let a: BTreeSet<u32> = (0..rng.gen_range(0..2000))
+                .map(|_| rng.gen_range(0..3000))
+                .collect();
+            let b: BTreeSet<u32> = (0..rng.gen_range(0..2000))
+                .map(|_| rng.gen_range(0..3000))
+                .collect();
+            let expected = a.len() + b.len() - a.intersection(&b).count();
+
+            // every combination of sorted sets and roaring bitmaps gives the same distances and unions
+            let representations = |set: &BTreeSet<u32>| {
+                [
+                    CategorySet::Sorted(set.iter().copied().collect()),
+                    CategorySet::Roaring(set.iter().copied().collect()),
+                ]
+            };
+            for x in representations(&a) {
+                for y in representations(&b) {
+                    assert!(x.len() + y.len() - x.intersection_len(&y) == expected);
+                    let mut union = x.clone();
+                    union.extend(&y);
+                    assert!(union.len() == a.union(&b).count());
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,211 @@
+use crate::compact::CompactDendrogram;
+use crate::dendrogram::{Dendrogram, Hierarchy};
+use crate::error::ClusteringError;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// A row of the linkage matrix: the ids of the two merged clusters, the distance and the size.
+pub(crate) type Merge = (usize, usize, f32, usize);
+
+/// A merge whose children have all been numbered, waiting in a min-heap by distance, then by key.
+struct Ready {
+    distance: f32,
+    key: usize,
+    index: usize,
+}
+
+impl PartialEq for Ready {
+    fn eq(&self, other: &Ready) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ready {}
+
+impl PartialOrd for Ready {
+    fn partial_cmp(&self, other: &Ready) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ready {
+    fn cmp(&self, other: &Ready) -> Ordering {
+        // Reverse the order for a min-heap
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then(other.key.cmp(&self.key))
+    }
+}
+
+/// A child of a merge: a row, or the index of another merge in post-order.
+#[derive(Clone, Copy)]
+enum Child {
+    Row(usize),
+    Merge(usize),
+}
+
+/// List the merges of the dendrogram in the order of a linkage matrix: by increasing distance, children before parents.
+/// Leaves are numbered by their row index, and the cluster created by the i-th merge is numbered n + i.
+///
+/// Merges at the same distance are listed by increasing key, given by `key` from the node and its index in post-order.
+pub(crate) fn merge_order<H, K>(dendrogram: H, key: K) -> Vec<Merge>
+where
+    H: Hierarchy,
+    K: Fn(H::Node, usize) -> usize,
+{
+    let root = dendrogram.root();
+    let num_rows = dendrogram.size(root);
+
+    // number the merges in post-order
+    let mut children: Vec<[Child; 2]> = Vec::with_capacity(num_rows - 1);
+    let mut distances: Vec<f32> = Vec::with_capacity(num_rows - 1);
+    let mut sizes: Vec<usize> = Vec::with_capacity(num_rows - 1);
+    let mut keys: Vec<usize> = Vec::with_capacity(num_rows - 1);
+    let mut parents: Vec<usize> = Vec::with_capacity(num_rows - 1);
+    // the number of children of each merge that are merges themselves and haven't been listed yet
+    let mut pending: Vec<u8> = Vec::with_capacity(num_rows - 1);
+    let mut values: Vec<Child> = Vec::new();
+    let mut stack = vec![(root, false)];
+    while let Some((current, expanded)) = stack.pop() {
+        match dendrogram.children(current) {
+            None => values.push(Child::Row(dendrogram.row_index(current).unwrap())),
+            Some((cluster1, cluster2)) if !expanded => {
+                stack.push((current, true));
+                stack.push((cluster2, false));
+                stack.push((cluster1, false));
+            }
+            Some(_) => {
+                let child2 = values.pop().unwrap();
+                let child1 = values.pop().unwrap();
+                let index = children.len();
+                let mut count = 0;
+                for child in [child1, child2] {
+                    if let Child::Merge(c) = child {
+                        parents[c] = index;
+                        count += 1;
+                    }
+                }
+                children.push([child1, child2]);
+                distances.push(dendrogram.distance(current));
+                sizes.push(dendrogram.size(current));
+                keys.push(key(current, index));
+                parents.push(usize::MAX);
+                pending.push(count);
+                values.push(Child::Merge(index));
+            }
+        }
+    }
+
+    // list the merges whose children have been listed, the lowest distance first
+    let mut heap: BinaryHeap<Ready> = (0..children.len())
+        .filter(|index| pending[*index] == 0)
+        .map(|index| Ready {
+            distance: distances[index],
+            key: keys[index],
+            index,
+        })
+        .collect();
+    let mut ids = vec![0; children.len()];
+    let mut merges = Vec::with_capacity(children.len());
+    while let Some(Ready {
+        distance, index, ..
+    }) = heap.pop()
+    {
+        ids[index] = num_rows + merges.len();
+        let [id1, id2] = children[index].map(|child| match child {
+            Child::Row(row_index) => row_index,
+            Child::Merge(c) => ids[c],
+        });
+        merges.push((id1, id2, distance, sizes[index]));
+
+        let parent = parents[index];
+        if parent != usize::MAX {
+            pending[parent] -= 1;
+            if pending[parent] == 0 {
+                heap.push(Ready {
+                    distance: distances[parent],
+                    key: keys[parent],
+                    index: parent,
+                });
+            }
+        }
+    }
+    merges
+}
+
+fn to_matrix(merges: Vec<Merge>) -> Vec<[f64; 4]> {
+    merges
+        .into_iter()
+        .map(|(id1, id2, distance, size)| [id1 as f64, id2 as f64, distance as f64, size as f64])
+        .collect()
+}
+
+impl Dendrogram {
+    /// Convert the dendrogram into a SciPy-style linkage matrix.
+    ///
+    /// The matrix has one row `[idx1, idx2, distance, size]` per merge. Leaves are numbered by their row index, from 0 to n - 1,
+    /// and the cluster created by the i-th row of the matrix is numbered n + i. As in SciPy, the merges are listed by increasing
+    /// distance. Merges at the same distance, and merges whose distance is lower than one of their children's, are listed after
+    /// their children, so each row only refers to leaves or to clusters created by previous rows.
+    ///
+    /// The leaves must be numbered from 0 to n - 1, which is always the case for the dendrograms returned by create_dendrogram.
+    pub fn to_linkage_matrix(&self) -> Vec<[f64; 4]> {
+        to_matrix(merge_order(self, |_, post_order| post_order))
+    }
+
+    /// Build a dendrogram from a SciPy-style linkage matrix. See [`Dendrogram::to_linkage_matrix`] for the format.
+    ///
+    /// An empty matrix makes a dendrogram with a single row.
+    ///
+    /// # Errors
+    ///
+    /// [`ClusteringError::InvalidLinkageMatrix`] if a row refers to a cluster that doesn't exist yet or that has already been merged,
+    /// or if the size column doesn't match the sizes of the merged clusters.
+    pub fn from_linkage_matrix(matrix: &[[f64; 4]]) -> Result<Dendrogram, ClusteringError> {
+        let num_rows = matrix.len() + 1;
+        let mut clusters: Vec<Option<Dendrogram>> = (0..num_rows)
+            .map(|row_index| Some(Dendrogram::Leaf(row_index)))
+            .collect();
+
+        for (i, [idx1, idx2, distance, size]) in matrix.iter().enumerate() {
+            let mut take = |idx: f64| {
+                let valid = idx >= 0.0 && idx.fract() == 0.0 && (idx as usize) < clusters.len();
+                match clusters.get_mut(idx as usize).and_then(Option::take) {
+                    Some(cluster) if valid => Ok(cluster),
+                    _ => Err(ClusteringError::InvalidLinkageMatrix(format!(
+                        "row {} refers to cluster {}, which is not available",
+                        i, idx
+                    ))),
+                }
+            };
+            let cluster1 = take(*idx1)?;
+            let cluster2 = take(*idx2)?;
+            let new_size = cluster1.size() + cluster2.size();
+            if *size != new_size as f64 {
+                return Err(ClusteringError::InvalidLinkageMatrix(format!(
+                    "row {} has size {}, but the merged clusters have {} rows",
+                    i, size, new_size
+                )));
+            }
+            clusters.push(Some(Dendrogram::Node(
+                Box::new(cluster1),
+                Box::new(cluster2),
+                *distance as f32,
+                new_size,
+            )));
+        }
+
+        Ok(clusters.pop().unwrap().unwrap())
+    }
+}
+
+impl CompactDendrogram {
+    /// Convert the dendrogram into a SciPy-style linkage matrix. See [`Dendrogram::to_linkage_matrix`] for the format.
+    ///
+    /// Merges at the same distance are listed in the order of their nodes, so if the distance never decreases from a node
+    /// to the next, the i-th row of the matrix is the node n + i.
+    pub fn to_linkage_matrix(&self) -> Vec<[f64; 4]> {
+        to_matrix(merge_order(self, |node, _| node))
+    }
+}
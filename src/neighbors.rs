@@ -0,0 +1,377 @@
+use crate::data::TypedIndexableData;
+use crate::error::ClusteringError;
+use crate::sort_keys::{compare_values, SortKeys};
+use rand::seq::SliceRandom;
+use rand::RngCore;
+use std::collections::{HashMap, HashSet};
+
+/// The trait you need to implement to provide the algorithm with candidate neighbors.
+///
+/// Only the returned pairs of rows are linked when the clustering starts. Clusters that are not
+/// linked, directly or through previous merges, are linked together once all the candidate links
+/// have been consumed, so the returned graph does not need to be connected.
+pub trait NeighborFinder {
+    /// Returns pairs of row indices `(i, j)` with `i < j`, or an error if the finder's parameters are invalid.
+    /// Any other pair makes the clustering fail with [`ClusteringError::InvalidParameter`].
+    fn find_neighbors<D, R>(
+        &self,
+        data: &D,
+        rng: &mut R,
+    ) -> Result<HashSet<(usize, usize)>, ClusteringError>
+    where
+        D: TypedIndexableData,
+        R: RngCore;
+}
+
+/// The default initialization: sort the rows under shuffled column orders and link adjacent rows.
+///
+/// This is roughly equivalent to identifying neighbors through random projections onto a one-dimensional line.
+pub struct SortedProjection {
+    /// The number of initializing iterations. Each iteration sorts the data once per column.
+    pub init_iterations: i32,
+    /// The number of following rows each row is linked with in every sorted order.
+    /// With a window larger than 1, long runs of rows with identical prefixes don't turn into a chain.
+    pub window: usize,
+    /// Whether to extract the columns once into compact integer codes and sort the rows with a radix sort.
+    /// This is much faster than calling `value` in every comparison, at the cost of 1 to 4 bytes per value.
+    /// The rows are sorted in the same order either way, but rows with equal values may be ordered differently,
+    /// so this is disabled by default to keep the neighbors of previous versions.
+    pub precompute_keys: bool,
+}
+
+impl Default for SortedProjection {
+    fn default() -> Self {
+        SortedProjection {
+            init_iterations: 1,
+            window: 1,
+            precompute_keys: false,
+        }
+    }
+}
+
+impl NeighborFinder for SortedProjection {
+    fn find_neighbors<D, R>(
+        &self,
+        data: &D,
+        rng: &mut R,
+    ) -> Result<HashSet<(usize, usize)>, ClusteringError>
+    where
+        D: TypedIndexableData,
+        R: RngCore,
+    {
+        if self.init_iterations < 1 {
+            return Err(ClusteringError::InvalidParameter(
+                "Number of initilizating iterations must be at least 1.".to_string(),
+            ));
+        }
+        if self.window < 1 {
+            return Err(ClusteringError::InvalidParameter(
+                "Window must be at least 1.".to_string(),
+            ));
+        }
+
+        let col_orders = column_orders(data.num_columns(), self.init_iterations, rng);
+        let keys = if self.precompute_keys {
+            Some(SortKeys::extract(data))
+        } else {
+            None
+        };
+
+        #[cfg(not(feature = "rayon"))]
+        let neighbors = link_sorted_rows(data, keys.as_ref(), &col_orders, self.window);
+        #[cfg(feature = "rayon")]
+        let neighbors = par_link_sorted_rows(data, keys.as_ref(), &col_orders, self.window);
+
+        Ok(neighbors)
+    }
+}
+
+/// The column order of every sort. They are drawn upfront so that the rng is used the same way with or without rayon.
+pub(crate) fn column_orders<R: RngCore>(
+    num_columns: usize,
+    init_iterations: i32,
+    rng: &mut R,
+) -> Vec<Vec<usize>> {
+    let mut col_orders: Vec<Vec<usize>> = Vec::new();
+    let mut col_indices: Vec<usize> = (0..num_columns).collect();
+    for _ in 0..init_iterations {
+        for c in 0..num_columns {
+            col_indices.shuffle(rng);
+
+            // we move the column at hand to the last position to make sure we always explore this configuration despite the shuffle
+            for k in 0..num_columns {
+                if col_indices[c] == k {
+                    col_indices[c] = col_indices[num_columns - 1];
+                    col_indices[num_columns - 1] = k;
+                    break;
+                }
+            }
+            col_orders.push(col_indices.clone());
+        }
+    }
+    col_orders
+}
+
+/// Sort the rows along every column order and link the rows that are close in the sorted orders.
+#[cfg(any(not(feature = "rayon"), test))]
+pub(crate) fn link_sorted_rows<D: TypedIndexableData>(
+    data: &D,
+    keys: Option<&SortKeys>,
+    col_orders: &[Vec<usize>],
+    window: usize,
+) -> HashSet<(usize, usize)> {
+    let mut neighbors: HashSet<(usize, usize)> = HashSet::new();
+    let mut row_indices: Vec<usize> = Vec::new();
+    let mut buffer = Vec::new();
+    for col_order in col_orders {
+        sort_and_link(
+            data,
+            keys,
+            col_order,
+            window,
+            &mut row_indices,
+            &mut buffer,
+            &mut neighbors,
+        );
+    }
+    neighbors
+}
+
+/// Same as [`link_sorted_rows`], with the sorts spread over rayon's workers. Each worker reuses its own rows, buffer
+/// and neighbors across its sorts, so the memory doesn't grow with the number of sorts.
+#[cfg(feature = "rayon")]
+pub(crate) fn par_link_sorted_rows<D: TypedIndexableData>(
+    data: &D,
+    keys: Option<&SortKeys>,
+    col_orders: &[Vec<usize>],
+    window: usize,
+) -> HashSet<(usize, usize)> {
+    use rayon::prelude::*;
+
+    col_orders
+        .par_iter()
+        .fold(
+            || (HashSet::new(), Vec::new(), Vec::new()),
+            |(mut neighbors, mut row_indices, mut buffer), col_order| {
+                sort_and_link(
+                    data,
+                    keys,
+                    col_order,
+                    window,
+                    &mut row_indices,
+                    &mut buffer,
+                    &mut neighbors,
+                );
+                (neighbors, row_indices, buffer)
+            },
+        )
+        .map(|(neighbors, _, _)| neighbors)
+        .reduce(HashSet::new, |mut neighbors, mut other| {
+            if neighbors.len() < other.len() {
+                std::mem::swap(&mut neighbors, &mut other);
+            }
+            neighbors.extend(other);
+            neighbors
+        })
+}
+
+/// Sort the rows along the column order and link them with [`link_window`]. Every sort starts from the order of
+/// the row indices, so that the neighbors don't depend on which sorts came before.
+fn sort_and_link<D: TypedIndexableData>(
+    data: &D,
+    keys: Option<&SortKeys>,
+    col_order: &[usize],
+    window: usize,
+    row_indices: &mut Vec<usize>,
+    buffer: &mut Vec<usize>,
+    neighbors: &mut HashSet<(usize, usize)>,
+) {
+    row_indices.clear();
+    row_indices.extend(0..data.num_rows());
+    sort_rows(data, keys, col_order, row_indices, buffer);
+    link_window(row_indices, window, neighbors);
+}
+
+fn sort_rows<D: TypedIndexableData>(
+    data: &D,
+    keys: Option<&SortKeys>,
+    col_order: &[usize],
+    row_indices: &mut [usize],
+    buffer: &mut Vec<usize>,
+) {
+    if let Some(keys) = keys {
+        keys.sort_rows(col_order, row_indices, buffer);
+        return;
+    }
+    row_indices.sort_unstable_by(|i, j| {
+        for c in col_order {
+            let ordering = compare_values(data.value(*i, *c), data.value(*j, *c));
+            if ordering != std::cmp::Ordering::Equal {
+                return ordering;
+            }
+        }
+        std::cmp::Ordering::Equal
+    });
+}
+
+/// Link each row with the `window` rows that follow it in the sorted order.
+fn link_window(row_indices: &[usize], window: usize, neighbors: &mut HashSet<(usize, usize)>) {
+    let num_rows = row_indices.len();
+    for i in 0..num_rows {
+        for j in i + 1..num_rows.min(i + 1 + window) {
+            let mut row1 = row_indices[i];
+            let mut row2 = row_indices[j];
+            if row1 > row2 {
+                std::mem::swap(&mut row1, &mut row2);
+            }
+            neighbors.insert((row1, row2));
+        }
+    }
+}
+
+/// Candidate generation by MinHash banding over the categories of each row, as returned by [`TypedIndexableData::tokens`].
+///
+/// Each row gets a signature of `num_bands * rows_per_band` min-hashes. Rows whose signatures collide in at least
+/// one band are likely to share many categories and become candidate neighbors, whatever their column order.
+pub struct MinHash {
+    /// The number of bands. More bands produce more candidate pairs.
+    pub num_bands: usize,
+    /// The number of min-hashes per band. More min-hashes per band make collisions more selective.
+    pub rows_per_band: usize,
+}
+
+impl Default for MinHash {
+    fn default() -> Self {
+        MinHash {
+            num_bands: 16,
+            rows_per_band: 2,
+        }
+    }
+}
+
+/// splitmix64 finalizer, used as a family of hash functions by xoring in a seed.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+impl NeighborFinder for MinHash {
+    fn find_neighbors<D, R>(
+        &self,
+        data: &D,
+        rng: &mut R,
+    ) -> Result<HashSet<(usize, usize)>, ClusteringError>
+    where
+        D: TypedIndexableData,
+        R: RngCore,
+    {
+        if self.num_bands < 1 || self.rows_per_band < 1 {
+            return Err(ClusteringError::InvalidParameter(
+                "MinHash needs at least 1 band and 1 row per band.".to_string(),
+            ));
+        }
+
+        let num_rows = data.num_rows();
+        let num_hashes = self.num_bands * self.rows_per_band;
+        let seeds: Vec<u64> = (0..num_hashes).map(|_| rng.next_u64()).collect();
+
+        // signatures[row * num_hashes + h] is the h-th min-hash of the row
+        let mut signatures: Vec<u64> = vec![u64::MAX; num_rows * num_hashes];
+        let mut tokens = Vec::new();
+        for row in 0..num_rows {
+            tokens.clear();
+            data.tokens(row, &mut tokens);
+            let signature = &mut signatures[row * num_hashes..(row + 1) * num_hashes];
+            for token in &tokens {
+                for (min_hash, seed) in signature.iter_mut().zip(&seeds) {
+                    *min_hash = (*min_hash).min(mix(token ^ seed));
+                }
+            }
+        }
+
+        let mut neighbors: HashSet<(usize, usize)> = HashSet::new();
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+        for band in 0..self.num_bands {
+            buckets.clear();
+            for row in 0..num_rows {
+                let start = row * num_hashes + band * self.rows_per_band;
+                let key = signatures[start..start + self.rows_per_band]
+                    .iter()
+                    .fold(band as u64, |key, min_hash| mix(key ^ min_hash));
+                buckets.entry(key).or_default().push(row);
+            }
+            for bucket in buckets.values_mut() {
+                // linking every pair of a bucket would be quadratic, so we chain the rows in a random order
+                bucket.shuffle(rng);
+                for pair in bucket.windows(2) {
+                    neighbors.insert((pair[0].min(pair[1]), pair[0].max(pair[1])));
+                }
+            }
+        }
+
+        Ok(neighbors)
+    }
+}
+
+/// Exact initialization: every pair of rows is a candidate.
+///
+/// The number of pairs is quadratic in the number of rows. Each of the n(n-1)/2 pairs takes 60 to 80 bytes,
+/// between the returned `HashSet` and the links that seed the clustering, i.e. about 150 MB for 2,000 rows
+/// and 800 MB for 5,000 rows. This is only affordable for datasets of a couple of thousand rows.
+/// It gives a reference dendrogram to measure how much the approximate initializations lose.
+pub struct AllPairs;
+
+impl NeighborFinder for AllPairs {
+    fn find_neighbors<D, R>(
+        &self,
+        data: &D,
+        _rng: &mut R,
+    ) -> Result<HashSet<(usize, usize)>, ClusteringError>
+    where
+        D: TypedIndexableData,
+        R: RngCore,
+    {
+        let num_rows = data.num_rows();
+        let mut neighbors = HashSet::with_capacity(num_rows * num_rows.saturating_sub(1) / 2);
+        for row1 in 0..num_rows {
+            for row2 in row1 + 1..num_rows {
+                neighbors.insert((row1, row2));
+            }
+        }
+        Ok(neighbors)
+    }
+}
+
+/// `None` and `Some(init_iterations)` stand for [`SortedProjection`] with the given number of iterations (1 by default).
+impl NeighborFinder for Option<i32> {
+    fn find_neighbors<D, R>(
+        &self,
+        data: &D,
+        rng: &mut R,
+    ) -> Result<HashSet<(usize, usize)>, ClusteringError>
+    where
+        D: TypedIndexableData,
+        R: RngCore,
+    {
+        let finder = SortedProjection {
+            init_iterations: self.unwrap_or(1),
+            ..Default::default()
+        };
+        finder.find_neighbors(data, rng)
+    }
+}
+
+impl<N: NeighborFinder> NeighborFinder for &N {
+    fn find_neighbors<D, R>(
+        &self,
+        data: &D,
+        rng: &mut R,
+    ) -> Result<HashSet<(usize, usize)>, ClusteringError>
+    where
+        D: TypedIndexableData,
+        R: RngCore,
+    {
+        (**self).find_neighbors(data, rng)
+    }
+}
@@ -0,0 +1,224 @@
+use crate::dendrogram::Dendrogram;
+use crate::export::row_label;
+use std::io::{self, Write};
+
+/// The horizontal space given to each leaf or collapsed subtree in SVG drawings.
+const SVG_SPACING: f32 = 20.0;
+const SVG_MARGIN: f32 = 20.0;
+const SVG_PLOT_HEIGHT: f32 = 300.0;
+/// The space below the plot for the rotated labels.
+const SVG_LABEL_HEIGHT: f32 = 120.0;
+
+/// How [`Dendrogram::write_text`] and [`Dendrogram::write_svg`] truncate the dendrogram.
+///
+/// Subtrees deeper than `max_depth` or with at most `max_cluster_size` rows are collapsed into a single entry
+/// labelled with their size and merge distance.
+pub struct RenderOptions {
+    /// The depth below which subtrees are collapsed. The root is at depth 0.
+    pub max_depth: usize,
+    /// The size up to which subtrees are collapsed. With 1, only the depth truncates the dendrogram.
+    pub max_cluster_size: usize,
+    /// Whether the text rendering draws the branches with Unicode box-drawing characters rather than ASCII.
+    pub unicode: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            max_depth: 10,
+            max_cluster_size: 1,
+            unicode: true,
+        }
+    }
+}
+
+impl RenderOptions {
+    fn is_collapsed(&self, node: &Dendrogram, depth: usize) -> bool {
+        !node.is_leaf() && (depth >= self.max_depth || node.size() <= self.max_cluster_size)
+    }
+}
+
+fn collapsed_label(node: &Dendrogram) -> String {
+    format!("[{} rows, d={}]", node.size(), node.distance())
+}
+
+fn escape_xml(label: &str) -> String {
+    label
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Dendrogram {
+    /// Draw the dendrogram as an indented tree, one line per node, e.g.
+    ///
+    /// ```text
+    /// d=5 n=4
+    /// ├── d=1 n=2
+    /// │   ├── 0
+    /// │   └── 1
+    /// └── [2 rows, d=0.5]
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the text is written.
+    /// * `options` - Where to truncate the dendrogram, and which characters to draw the branches with.
+    /// * `labels` - Gives the label of each leaf from its row index. Row indices are used if `None`.
+    pub fn write_text<W: Write>(
+        &self,
+        mut writer: W,
+        options: &RenderOptions,
+        labels: Option<&dyn Fn(usize) -> String>,
+    ) -> io::Result<()> {
+        let (branch, last_branch, vertical) = if options.unicode {
+            ("├── ", "└── ", "│   ")
+        } else {
+            ("|-- ", "`-- ", "|   ")
+        };
+
+        // the node, its depth, the prefix of its line and the prefix of its children's lines
+        let mut stack = vec![(self, 0, String::new(), String::new())];
+        while let Some((current, depth, prefix, child_prefix)) = stack.pop() {
+            write!(writer, "{}", prefix)?;
+            match current {
+                Dendrogram::Leaf(row_index) => {
+                    writeln!(writer, "{}", row_label(*row_index, labels))?
+                }
+                _ if options.is_collapsed(current, depth) => {
+                    writeln!(writer, "{}", collapsed_label(current))?
+                }
+                Dendrogram::Node(cluster1, cluster2, distance, size) => {
+                    writeln!(writer, "d={} n={}", distance, size)?;
+                    stack.push((
+                        cluster2,
+                        depth + 1,
+                        format!("{}{}", child_prefix, last_branch),
+                        format!("{}    ", child_prefix),
+                    ));
+                    stack.push((
+                        cluster1,
+                        depth + 1,
+                        format!("{}{}", child_prefix, branch),
+                        format!("{}{}", child_prefix, vertical),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw the dendrogram as an SVG image, with the root at the top and the leaves at the bottom.
+    /// The height of each node is proportional to its merge distance.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the SVG document is written.
+    /// * `options` - Where to truncate the dendrogram. Collapsed subtrees are drawn as triangles.
+    /// * `labels` - Gives the label of each leaf from its row index. Row indices are used if `None`.
+    pub fn write_svg<W: Write>(
+        &self,
+        mut writer: W,
+        options: &RenderOptions,
+        labels: Option<&dyn Fn(usize) -> String>,
+    ) -> io::Result<()> {
+        // count the leaves and collapsed subtrees to size the image, and find the highest drawn node to scale it,
+        // which is not necessarily the root when the distances are not monotone
+        let mut num_slots = 0;
+        let mut max_distance: f32 = 0.0;
+        let mut stack = vec![(self, 0)];
+        while let Some((current, depth)) = stack.pop() {
+            max_distance = max_distance.max(current.distance());
+            match current.children() {
+                Some((cluster1, cluster2)) if !options.is_collapsed(current, depth) => {
+                    stack.push((cluster1, depth + 1));
+                    stack.push((cluster2, depth + 1));
+                }
+                _ => num_slots += 1,
+            }
+        }
+
+        let width = 2.0 * SVG_MARGIN + num_slots as f32 * SVG_SPACING;
+        let height = 2.0 * SVG_MARGIN + SVG_PLOT_HEIGHT + SVG_LABEL_HEIGHT;
+        let baseline = SVG_MARGIN + SVG_PLOT_HEIGHT;
+        let y = |distance: f32| {
+            if max_distance > 0.0 {
+                baseline - SVG_PLOT_HEIGHT * distance / max_distance
+            } else {
+                baseline
+            }
+        };
+
+        writeln!(
+            writer,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"10\">",
+            width, height
+        )?;
+        writeln!(writer, "<g stroke=\"black\" fill=\"none\">")?;
+
+        // post-order, with the positions of the drawn children on a stack
+        let mut slot = 0;
+        let mut positions: Vec<(f32, f32)> = Vec::new();
+        let mut stack = vec![(self, 0, false)];
+        while let Some((current, depth, expanded)) = stack.pop() {
+            let children = match current.children() {
+                Some(children) if !options.is_collapsed(current, depth) => Some(children),
+                _ => None,
+            };
+            match children {
+                Some((cluster1, cluster2)) if !expanded => {
+                    stack.push((current, depth, true));
+                    stack.push((cluster2, depth + 1, false));
+                    stack.push((cluster1, depth + 1, false));
+                }
+                Some(_) => {
+                    let (x2, y2) = positions.pop().unwrap();
+                    let (x1, y1) = positions.pop().unwrap();
+                    let top = y(current.distance());
+                    writeln!(
+                        writer,
+                        "<path d=\"M{} {} V{} H{} V{}\"/>",
+                        x1, y1, top, x2, y2
+                    )?;
+                    positions.push(((x1 + x2) / 2.0, top));
+                }
+                None => {
+                    let x = SVG_MARGIN + (slot as f32 + 0.5) * SVG_SPACING;
+                    slot += 1;
+                    let label = match current {
+                        Dendrogram::Leaf(row_index) => row_label(*row_index, labels),
+                        _ => collapsed_label(current),
+                    };
+                    let top = y(current.distance());
+                    if !current.is_leaf() {
+                        let half = 0.4 * SVG_SPACING;
+                        writeln!(
+                            writer,
+                            "<polygon points=\"{},{} {},{} {},{}\" fill=\"lightgray\"/>",
+                            x,
+                            top,
+                            x - half,
+                            baseline,
+                            x + half,
+                            baseline
+                        )?;
+                    }
+                    writeln!(
+                        writer,
+                        "<text x=\"{}\" y=\"{}\" transform=\"rotate(90 {} {})\" stroke=\"none\" fill=\"black\">{}</text>",
+                        x,
+                        baseline + 4.0,
+                        x,
+                        baseline + 4.0,
+                        escape_xml(&label)
+                    )?;
+                    positions.push((x, top));
+                }
+            }
+        }
+
+        writeln!(writer, "</g>")?;
+        writeln!(writer, "</svg>")
+    }
+}
@@ -0,0 +1,65 @@
+use crate::dendrogram::Dendrogram;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// An element of the flat representation of a dendrogram, which lists the nodes in post-order.
+/// Nodes come after their two children, so the tree can be rebuilt with a stack rather than with recursion.
+#[derive(Serialize, Deserialize)]
+enum FlatNode {
+    Leaf(usize),
+    /// The merge distance.
+    Node(f32),
+}
+
+impl Serialize for Dendrogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(2 * self.size() - 1))?;
+        for current in self.post_order() {
+            let flat = match current {
+                Dendrogram::Leaf(row_index) => FlatNode::Leaf(*row_index),
+                Dendrogram::Node(_, _, distance, _) => FlatNode::Node(*distance),
+            };
+            seq.serialize_element(&flat)?;
+        }
+        seq.end()
+    }
+}
+
+struct DendrogramVisitor;
+
+impl<'de> Visitor<'de> for DendrogramVisitor {
+    type Value = Dendrogram;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence of dendrogram nodes in post-order")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Dendrogram, A::Error> {
+        let mut stack: Vec<Dendrogram> = Vec::new();
+        while let Some(flat) = seq.next_element()? {
+            match flat {
+                FlatNode::Leaf(row_index) => stack.push(Dendrogram::Leaf(row_index)),
+                FlatNode::Node(distance) => {
+                    let (c2, c1) = match (stack.pop(), stack.pop()) {
+                        (Some(c2), Some(c1)) => (c2, c1),
+                        _ => return Err(de::Error::custom("a node has fewer than two children")),
+                    };
+                    let size = c1.size() + c2.size();
+                    stack.push(Dendrogram::Node(Box::new(c1), Box::new(c2), distance, size));
+                }
+            }
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some(root), true) => Ok(root),
+            _ => Err(de::Error::custom("the nodes don't make a single tree")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Dendrogram {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Dendrogram, D::Error> {
+        deserializer.deserialize_seq(DendrogramVisitor)
+    }
+}
@@ -0,0 +1,142 @@
+use crate::data::TypedIndexableData;
+use std::cmp::Ordering;
+
+/// Below this number of rows, a range is sorted by comparing keys rather than by counting.
+const SMALL_RANGE: usize = 32;
+
+/// The order of the values when sorting rows, with or without precomputed keys: the usual order of the numbers,
+/// -0.0 and 0.0 being equal, and NaN values equal to each other and greater than any number.
+pub(crate) fn compare_values(v1: f32, v2: f32) -> Ordering {
+    v1.partial_cmp(&v2)
+        .unwrap_or_else(|| v1.is_nan().cmp(&v2.is_nan()))
+}
+
+/// Dense codes of a column, stored in the narrowest integer type that fits its cardinality.
+enum ColumnCodes {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl ColumnCodes {
+    #[inline]
+    fn get(&self, row_index: usize) -> usize {
+        match self {
+            ColumnCodes::U8(codes) => codes[row_index] as usize,
+            ColumnCodes::U16(codes) => codes[row_index] as usize,
+            ColumnCodes::U32(codes) => codes[row_index] as usize,
+        }
+    }
+}
+
+/// The columns of the data, extracted once into integer codes that preserve the order of the values.
+/// Sorting rows with these keys avoids calling `value` and comparing floats in every comparison.
+pub(crate) struct SortKeys {
+    columns: Vec<ColumnCodes>,
+    cardinalities: Vec<usize>,
+}
+
+impl SortKeys {
+    pub(crate) fn extract<D: TypedIndexableData>(data: &D) -> SortKeys {
+        let num_rows = data.num_rows();
+        let num_columns = data.num_columns();
+        let mut columns = Vec::with_capacity(num_columns);
+        let mut cardinalities = Vec::with_capacity(num_columns);
+
+        let mut values: Vec<f32> = Vec::with_capacity(num_rows);
+        let mut distinct: Vec<f32> = Vec::new();
+        for c in 0..num_columns {
+            values.clear();
+            values.extend((0..num_rows).map(|r| data.value(r, c)));
+            distinct.clear();
+            distinct.extend_from_slice(&values);
+            distinct.sort_unstable_by(|a, b| compare_values(*a, *b));
+            distinct.dedup_by(|a, b| compare_values(*a, *b) == Ordering::Equal);
+
+            let code = |v: &f32| {
+                distinct
+                    .binary_search_by(|d| compare_values(*d, *v))
+                    .unwrap()
+            };
+            let codes = if distinct.len() <= u8::MAX as usize + 1 {
+                ColumnCodes::U8(values.iter().map(|v| code(v) as u8).collect())
+            } else if distinct.len() <= u16::MAX as usize + 1 {
+                ColumnCodes::U16(values.iter().map(|v| code(v) as u16).collect())
+            } else {
+                ColumnCodes::U32(values.iter().map(|v| code(v) as u32).collect())
+            };
+            columns.push(codes);
+            cardinalities.push(distinct.len());
+        }
+
+        SortKeys {
+            columns,
+            cardinalities,
+        }
+    }
+
+    fn compare(&self, col_order: &[usize], i: usize, j: usize) -> Ordering {
+        for c in col_order {
+            let ordering = self.columns[*c].get(i).cmp(&self.columns[*c].get(j));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Sort the rows lexicographically along the given column order.
+    ///
+    /// This is an MSD radix sort: each range of rows sharing the same prefix is counting-sorted by the next column,
+    /// unless the range is smaller than the column's cardinality, in which case it is sorted by comparing keys.
+    pub(crate) fn sort_rows(
+        &self,
+        col_order: &[usize],
+        row_indices: &mut [usize],
+        buffer: &mut Vec<usize>,
+    ) {
+        buffer.resize(row_indices.len(), 0);
+        let mut counts: Vec<usize> = Vec::new();
+        let mut stack = vec![(0, row_indices.len(), 0)];
+
+        while let Some((start, end, depth)) = stack.pop() {
+            if end - start < 2 || depth == col_order.len() {
+                continue;
+            }
+            let column = &self.columns[col_order[depth]];
+            let cardinality = self.cardinalities[col_order[depth]];
+            let range = &mut row_indices[start..end];
+            if range.len() < SMALL_RANGE || range.len() < cardinality {
+                range.sort_unstable_by(|i, j| self.compare(&col_order[depth..], *i, *j));
+                continue;
+            }
+
+            // counting sort by the column at hand
+            counts.clear();
+            counts.resize(cardinality + 1, 0);
+            for row in range.iter() {
+                counts[column.get(*row) + 1] += 1;
+            }
+            for k in 1..counts.len() {
+                counts[k] += counts[k - 1];
+            }
+            let sorted = &mut buffer[start..end];
+            for row in range.iter() {
+                let code = column.get(*row);
+                sorted[counts[code]] = *row;
+                counts[code] += 1;
+            }
+            range.copy_from_slice(sorted);
+
+            // after the scatter, counts[k] is the end of the k-th bucket
+            let mut bucket_start = start;
+            for bucket_end in &counts[..cardinality] {
+                let bucket_end = start + bucket_end;
+                if bucket_end - bucket_start > 1 {
+                    stack.push((bucket_start, bucket_end, depth + 1));
+                }
+                bucket_start = bucket_end;
+            }
+        }
+    }
+}